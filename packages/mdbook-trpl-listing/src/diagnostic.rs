@@ -0,0 +1,97 @@
+//! Errors which point at the place in a chapter's source that caused them.
+
+use std::{fmt, ops::Range, path::PathBuf};
+
+/// A problem with the listing markup in a chapter, along with where it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The chapter's source file, relative to the book's `src` directory, if
+    /// it has one.
+    pub source_path: Option<PathBuf>,
+    /// The byte offset of the problem in the chapter source.
+    pub offset: usize,
+    /// The 1-based line the problem is on.
+    pub line: usize,
+    /// The 1-based column (in characters) the problem starts at.
+    pub column: usize,
+    /// The full text of that line, without its line ending.
+    pub source_line: String,
+    /// How many characters of `source_line` the problem covers, at least 1.
+    pub width: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Point at `range` in the chapter source `src`.
+    pub(crate) fn new(
+        src: &str,
+        range: Range<usize>,
+        source_path: Option<PathBuf>,
+        message: impl Into<String>,
+    ) -> Diagnostic {
+        let offset = range.start.min(src.len());
+        let line_start = src[..offset].rfind('\n').map_or(0, |n| n + 1);
+        let line_end =
+            src[offset..].find('\n').map_or(src.len(), |n| offset + n);
+        let source_line = src[line_start..line_end].trim_end_matches('\r');
+
+        let column = src[line_start..offset].chars().count() + 1;
+        let end = range.end.min(line_start + source_line.len()).max(offset);
+        let width = src[offset..end].chars().count().max(1);
+
+        Diagnostic {
+            source_path,
+            offset,
+            line: src[..offset].matches('\n').count() + 1,
+            column,
+            source_line: source_line.to_string(),
+            width,
+            message: message.into(),
+        }
+    }
+
+    /// Where the problem is, like `ch02-00-guessing-game-tutorial.md:76`, or
+    /// `line 76` for a chapter without a source file.
+    pub fn location(&self) -> String {
+        match &self.source_path {
+            Some(source_path) => {
+                format!("{}:{}", source_path.display(), self.line)
+            }
+            None => format!("line {}", self.line),
+        }
+    }
+
+    /// Render the diagnostic the way `rustc` does, with the offending source
+    /// underlined, which for a tag is the whole tag:
+    ///
+    /// ```text
+    /// error: unknown attribute 'filename' on <Listing>: ...
+    ///  --> ch01.md:3:1
+    ///   |
+    /// 3 | <Listing number="1-1" filename="src/main.rs">
+    ///   | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+    /// ```
+    pub fn render(&self) -> String {
+        let path = match &self.source_path {
+            Some(source_path) => source_path.display().to_string(),
+            None => String::from("<chapter>"),
+        };
+        let line = self.line.to_string();
+        let gutter = " ".repeat(line.len());
+        let indent = " ".repeat(self.column - 1);
+        let underline = "^".repeat(self.width);
+
+        format!(
+            "error: {message}\n{gutter}--> {path}:{line}:{column}\n{gutter} |\n{line} | {source_line}\n{gutter} | {indent}{underline}\n",
+            message = self.message,
+            column = self.column,
+            source_line = self.source_line,
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location(), self.message)
+    }
+}
@@ -1,13 +1,32 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+};
+
 use html_parser::Dom;
 use mdbook::{
-    book::Book,
+    book::{Book, Chapter},
     errors::Result,
     preprocess::{Preprocessor, PreprocessorContext},
-    utils::new_cmark_parser,
-    BookItem,
+    utils::{
+        fs::path_to_root, take_anchored_lines,
+        take_rustdoc_include_anchored_lines, take_rustdoc_include_lines,
+    },
 };
-use pulldown_cmark::{html, Event};
-use pulldown_cmark_to_cmark::cmark;
+use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
+use trpl_preprocess::{
+    config::{self, ConfigTable},
+    rewrite::{inline_html, trim_end, Edit},
+    stage::{self, Changes, Parsed, Stage},
+    Mode, TrplPreprocessor,
+};
+
+pub mod diagnostic;
+pub mod migrate;
+
+use diagnostic::Diagnostic;
 
 /// A preprocessor for rendering listings more elegantly.
 ///
@@ -28,7 +47,7 @@ use pulldown_cmark_to_cmark::cmark;
 /// following Markdown to be further preprocessed or rendered to HTML:
 ///
 /// ````markdown
-/// <figure class="listing">
+/// <figure class="listing" id="listing-1-2">
 /// <span class="file-name">Filename: src/main.rs</span>
 ///
 /// ```rust
@@ -40,6 +59,10 @@ use pulldown_cmark_to_cmark::cmark;
 /// </figure>
 /// ````
 ///
+/// Only the listing markup itself is replaced, using its position in the
+/// chapter's source: everything else, including the code block, comes through
+/// byte for byte, so mdBook sees the same Markdown the author wrote.
+///
 /// When `output-mode = "simple"` in the configuration, it instead emits:
 ///
 /// ````markdown
@@ -51,6 +74,142 @@ use pulldown_cmark_to_cmark::cmark;
 ///
 /// Listing 1-2: Some *text*, yeah?
 /// ````
+///
+/// When `output-mode = "print"`, it emits Pandoc-style fenced divs instead,
+/// leaving the caption as Markdown so a print pipeline (for example, Pandoc
+/// to LaTeX) can typeset it however it needs to:
+///
+/// ````markdown
+/// :::: {#listing-1-2 .listing number="1-2" file="src/main.rs"}
+///
+/// ```rust
+/// fn main() {}
+/// ```
+///
+/// ::: caption
+/// Some *text*, yeah?
+/// :::
+/// ::::
+/// ````
+///
+/// `output-mode` can also be a table of renderer names to modes, like
+/// `{ html = "default", markdown = "simple" }`, in which case any renderer not
+/// in the table gets the default mode.
+///
+/// Instead of a hand-written `number`, a listing can have an `id`, in which
+/// case its number is computed from the position of its chapter in
+/// `SUMMARY.md` and the listings which precede it in that chapter:
+///
+/// ````markdown
+/// <Listing id="guess-input" file-name="src/main.rs" caption="Getting a guess">
+///
+/// ```rust
+/// fn main() {}
+/// ```
+///
+/// </Listing>
+/// ````
+///
+/// Prose anywhere in the book can then refer to that listing with
+/// `<ListingRef id="guess-input" />`, which becomes a link like
+/// `<a href="ch02-00-guessing-game-tutorial.html#listing-2-1">Listing 2-1</a>`
+/// in the default mode, plain `Listing 2-1` in the simple mode, and
+/// `[Listing 2-1](#listing-2-1)` in the print mode.
+///
+/// Instead of containing a code block, a listing can name the file to take its
+/// code from, relative to the root of the book, along with an optional anchor:
+///
+/// ````markdown
+/// <Listing number="2-1" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs" anchor="all">
+/// </Listing>
+/// ````
+///
+/// The code block is then generated from that file, with its language taken
+/// from the file extension, exactly as `{{#rustdoc_include path:anchor}}` (for
+/// Rust) or `{{#include path:anchor}}` (for anything else) would. The listing
+/// directory must follow the naming rules in `ADMIN_TASKS.md`, so the example
+/// above must be in `listing-02-01`.
+///
+/// To draw attention to the lines which changed since an earlier listing, a
+/// listing can highlight some lines of its code and dim others, using 1-based
+/// line numbers and ranges:
+///
+/// ````markdown
+/// <Listing number="2-3" highlight="3-5,9" dim="1-2">
+/// ````
+///
+/// Lines count the way a reader sees them, skipping hidden `# ` lines in Rust,
+/// so this works the same for code from `{{#rustdoc_include}}`. In the default
+/// mode, the code block is emitted as HTML with each line wrapped in a
+/// `<span class="line">`, with an extra `highlighted` or `dimmed` class, and
+/// with `snip` for `// --snip--` lines. In the simple mode, each line instead
+/// gets a marker in the margin: `+` for highlighted, `.` for dimmed, and `~`
+/// for snipped. The print mode passes `highlight` and `dim` along as attributes.
+///
+/// A `line-numbers` attribute numbers the lines of the code in the gutter,
+/// starting from 1, or from the given number with `line-numbers="7"`. With
+/// `line-numbers="source"`, each line gets its number in the file it came
+/// from, which only works for code from a file: either through `src`, or a
+/// code block which is nothing but an `{{#rustdoc_include}}` or `{{#include}}`
+/// (which requires running this preprocessor `before = ["links"]`, so that it
+/// sees the include before mdBook expands it).
+///
+/// A listing of the output of running another listing's code, usually a
+/// `console` block from its `output.txt`, can say which listing it belongs to:
+///
+/// ````markdown
+/// <Listing kind="output" for="2-4">
+/// ````
+///
+/// `for` is either the listing's number or its `id`, and must refer to a
+/// listing which exists. The caption then starts with “Output of Listing 2-4”
+/// (linked to that listing, in the default mode), and the figure gets an
+/// `output` class. When the listing's code block is marked `does_not_compile`,
+/// the compiler errors are collapsed in a `<details>` element in the default
+/// mode.
+///
+/// A listing which shows several files, like a workspace, wraps each file's
+/// code in a `<File>` element instead of using `file-name`:
+///
+/// ````markdown
+/// <Listing number="14-7" caption="A workspace with two crates">
+///
+/// <File name="adder/src/main.rs">
+///
+/// ```rust
+/// fn main() {}
+/// ```
+///
+/// </File>
+///
+/// </Listing>
+/// ````
+///
+/// Each file gets its own `Filename: ...` header under the one caption. In
+/// the default mode, each is wrapped in `<div class="listing-file">`; in the
+/// print mode, in a `::: {.file name="..."}` div. `highlight`, `dim`, and
+/// `line-numbers` apply to each of the files' code blocks.
+///
+/// Rather than typing flags like `ignore,does_not_compile` into the info
+/// string of a listing's code block, it can say what they mean:
+///
+/// | Attribute            | Flags added                  |
+/// |----------------------|------------------------------|
+/// | `compiles="no"`      | `ignore`, `does_not_compile` |
+/// | `panics="yes"`       | `should_panic`, `panics`     |
+/// | `playground="false"` | `noplayground`               |
+///
+/// Flags the code block already has are left alone, and the opposite values
+/// (`compiles="yes"` and so on) add nothing, but both are errors if the info
+/// string has a flag which contradicts them, like `does_not_compile` with
+/// `compiles="yes"`.
+///
+/// With `permalinks = true`, the “Listing 1-2” in the caption also links to
+/// the figure itself: `<a href="#listing-1-2">Listing 1-2</a>`.
+///
+/// With `list-of-listings = "some/path.md"`, it also fills in the chapter at
+/// that path with a table of every numbered listing in the book, or adds a
+/// new chapter at that path if there is not one already.
 pub struct TrplListing;
 
 impl Preprocessor for TrplListing {
@@ -58,47 +217,110 @@ impl Preprocessor for TrplListing {
         "trpl-listing"
     }
 
-    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let config = ctx
-            .config
-            .get_preprocessor(self.name())
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
+        let table = ConfigTable::from_context(ctx, self.name())
             .ok_or(Error::NoConfig)?;
+        stage::run(ctx, book, &[("listing", self, Some(table))])
+    }
 
-        let key = String::from("output-mode");
-        let mode = config
-            .get(&key)
-            .map(|value| match value.as_str() {
-                Some(s) => Mode::try_from(s).map_err(|_| Error::BadValue {
-                    key,
-                    value: value.to_string(),
-                }),
-                None => Err(Error::BadValue {
-                    key,
-                    value: value.to_string(),
-                }),
-            })
-            .transpose()?
-            .unwrap_or(Mode::Default);
-
-        let mut errors: Vec<String> = vec![];
-        book.for_each_mut(|item| {
-            if let BookItem::Chapter(ref mut chapter) = item {
-                match rewrite_listing(&chapter.content, mode) {
-                    Ok(rewritten) => chapter.content = rewritten,
-                    Err(reason) => errors.push(reason),
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        trpl_preprocess::supports_renderer(renderer)
+    }
+}
+
+impl TrplPreprocessor for TrplListing {
+    fn report(&self, error: mdbook::errors::Error) -> String {
+        Stage::report(self, &error).unwrap_or_else(|| format!("{error}"))
+    }
+}
+
+impl Stage for TrplListing {
+    fn rewrite(
+        &self,
+        ctx: &PreprocessorContext,
+        config: Option<ConfigTable<'_>>,
+        chapters: &[Parsed<'_>],
+    ) -> Result<Changes> {
+        let (config, list_of_listings) = match config {
+            Some(table) => {
+                let config = Config {
+                    mode: table.mode()?,
+                    permalinks: table.bool("permalinks")?.unwrap_or(false),
+                    ..Config::default()
+                };
+                let list_of_listings =
+                    table.str("list-of-listings")?.map(PathBuf::from);
+                (config, list_of_listings)
+            }
+            None => (Config::default(), None),
+        };
+        let config = Config {
+            style_lines: ctx.renderer != "test",
+            ..config
+        };
+
+        let index = ListingIndex::build(chapters).map_err(CompositeError)?;
+
+        let src_dir = ctx.root.join(&ctx.config.book.src);
+        let mut changes = Changes::default();
+        let mut errors: Vec<Diagnostic> = vec![];
+        for parsed in chapters {
+            let info = ChapterInfo {
+                root: Some(&ctx.root),
+                src_dir: Some(&src_dir),
+                ..ChapterInfo::from(parsed.chapter)
+            };
+            let src = parsed.src();
+            match listing_edits(src, &parsed.events, config, &index, info) {
+                Ok(edits) => changes.edits.push(edits),
+                Err(diagnostics) => {
+                    errors.extend(diagnostics);
+                    changes.edits.push(vec![]);
                 }
             }
-        });
+        }
 
-        if errors.is_empty() {
-            Ok(book)
-        } else {
-            Err(CompositeError(errors.join("\n")).into())
+        if !errors.is_empty() {
+            return Err(CompositeError(errors).into());
+        }
+
+        if let Some(path) = list_of_listings {
+            let mut replaced_placeholder = false;
+            for (parsed, edits) in chapters.iter().zip(&mut changes.edits) {
+                if parsed.chapter.path.as_ref() == Some(&path) {
+                    *edits = vec![Edit {
+                        span: 0..parsed.src().len(),
+                        text: index
+                            .list_of_listings(&parsed.chapter.name, &path),
+                    }];
+                    replaced_placeholder = true;
+                }
+            }
+
+            if !replaced_placeholder {
+                let name = "List of Listings";
+                let content = index.list_of_listings(name, &path);
+                changes.chapters.push(Chapter::new(
+                    name,
+                    content,
+                    &path,
+                    vec![],
+                ));
+            }
         }
+
+        Ok(changes)
     }
 
-    fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html" || renderer == "markdown" || renderer == "test"
+    /// Print any listing errors with their source snippets, and summarize them
+    /// in the message.
+    fn report(&self, error: &mdbook::errors::Error) -> Option<String> {
+        let composite = error.downcast_ref::<CompositeError>()?;
+        let diagnostics = composite.diagnostics();
+        for diagnostic in diagnostics {
+            eprintln!("{}", diagnostic.render());
+        }
+        Some(format!("{} error(s) rewriting listings", diagnostics.len()))
     }
 }
 
@@ -107,280 +329,1985 @@ enum Error {
     #[error("No config for trpl-listing")]
     NoConfig,
 
-    #[error("Bad config value '{value}' for key '{key}'")]
-    BadValue { key: String, value: String },
+    #[error(transparent)]
+    Config(#[from] config::Error),
 }
 
-#[derive(Debug, thiserror::Error)]
-#[error("Error(s) rewriting input: {0}")]
-struct CompositeError(String);
+/// Every problem found with the listings in a book.
+#[derive(Debug)]
+pub struct CompositeError(Vec<Diagnostic>);
 
-#[derive(Debug, Clone, Copy)]
-enum Mode {
-    Default,
-    Simple,
+impl CompositeError {
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.0
+    }
 }
 
-/// Trivial marker struct to indicate an internal error.
-///
-/// The caller has enough info to do what it needs without passing data around.
-struct ParseErr;
-
-impl TryFrom<&str> for Mode {
-    type Error = ParseErr;
-
-    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
-        match value {
-            "default" => Ok(Mode::Default),
-            "simple" => Ok(Mode::Simple),
-            _ => Err(ParseErr),
+impl std::fmt::Display for CompositeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error(s) rewriting input:")?;
+        for diagnostic in &self.0 {
+            write!(f, "\n{diagnostic}")?;
         }
+        Ok(())
     }
 }
 
-fn rewrite_listing(src: &str, mode: Mode) -> Result<String, String> {
-    let final_state = new_cmark_parser(src, true).try_fold(
-        ListingState {
-            current: None,
-            events: vec![],
-        },
-        |mut state, ev| {
-            match ev {
-                Event::Html(tag) => {
-                    if tag.starts_with("<Listing") {
-                        state.open_listing(tag, mode)?;
-                    } else if tag.starts_with("</Listing>") {
-                        state.close_listing(tag, mode);
-                    } else {
-                        state.events.push(Ok(Event::Html(tag)));
-                    }
-                }
-                ev => state.events.push(Ok(ev)),
-            };
-            Ok::<ListingState<'_>, String>(state)
-        },
-    )?;
-
-    if final_state.current.is_some() {
-        return Err("Unclosed listing".into());
-    }
+impl std::error::Error for CompositeError {}
 
-    let (events, errors): (Vec<_>, Vec<_>) =
-        final_state.events.into_iter().partition(|e| e.is_ok());
+/// The options from `[preprocessor.trpl-listing]` which affect how each
+/// listing is rendered.
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    /// `output-mode`: whether to emit HTML, plain Markdown, or Markdown with
+    /// semantic markers for print.
+    mode: Mode,
+    /// `permalinks`: whether to link each listing's caption to the listing
+    /// itself, so readers can easily grab a link to it. Only meaningful for
+    /// [`Mode::Default`].
+    permalinks: bool,
+    /// Whether to render code blocks in listings with `highlight`, `dim`, or
+    /// `line-numbers` line by line. Not for mdBook's `test` renderer, which
+    /// only tests the code blocks rustdoc can see, so they have to stay fenced
+    /// code blocks.
+    style_lines: bool,
+}
 
-    if !errors.is_empty() {
-        return Err(errors
-            .into_iter()
-            .map(|e| e.unwrap_err())
-            .collect::<Vec<String>>()
-            .join("\n"));
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::Default,
+            permalinks: false,
+            style_lines: true,
+        }
     }
+}
 
-    let mut buf = String::with_capacity(src.len() * 2);
-    cmark(events.into_iter().map(|ok| ok.unwrap()), &mut buf)
-        .map_err(|e| format!("{e}"))?;
-    Ok(buf)
+/// Every numbered listing in the book, along with the chapter it lives in, and
+/// the numbers listings with an `id` end up with.
+///
+/// This has to be built up front from the whole book, rather than as each
+/// chapter is rewritten, because a `<ListingRef>` can point to a listing in a
+/// later chapter.
+#[derive(Debug, Default)]
+struct ListingIndex {
+    /// The number of each listing with an `id`.
+    by_id: HashMap<String, String>,
+    by_number: HashMap<String, Target>,
+    /// Every numbered listing, in book order.
+    listings: Vec<Entry>,
 }
 
-struct ListingState<'e> {
-    current: Option<Listing>,
-    events: Vec<Result<Event<'e>, String>>,
+#[derive(Debug)]
+struct Target {
+    number: String,
+    path: Option<PathBuf>,
+    /// Whether the listing's code block is marked `does_not_compile`, so that
+    /// the output for it is a wall of compiler errors.
+    does_not_compile: bool,
 }
 
-impl<'e> ListingState<'e> {
-    fn open_listing(
-        &mut self,
-        tag: pulldown_cmark::CowStr<'_>,
-        mode: Mode,
-    ) -> Result<(), String> {
-        // We do not *keep* the version constructed here, just temporarily
-        // construct it so the HTML parser, which expects properly closed tags
-        // to parse it as a *tag* rather than a *weird text node*, which accept
-        // it and provide a useful view of it.
-        let to_parse = tag.to_owned().to_string() + "</Listing>";
-        let listing = Dom::parse(&to_parse)
-            .map_err(|e| e.to_string())?
-            .children
-            .into_iter()
-            .filter_map(|node| match node {
-                html_parser::Node::Element(element) => Some(element.attributes),
-                html_parser::Node::Text(_) | html_parser::Node::Comment(_) => {
-                    None
-                }
-            })
-            .flatten()
-            .try_fold(ListingBuilder::new(), |builder, (key, maybe_value)| {
-                match (key.as_str(), maybe_value) {
-                    ("number", Some(value)) => Ok(builder.with_number(value)),
-                    ("number", None) => {
-                        Err(String::from("number attribute without value"))
+/// A numbered listing, and the chapter it appears in.
+#[derive(Debug)]
+struct Entry {
+    listing: Listing,
+    chapter_name: String,
+    path: Option<PathBuf>,
+}
+
+impl ListingIndex {
+    fn build(chapters: &[Parsed<'_>]) -> Result<ListingIndex, Vec<Diagnostic>> {
+        let mut index = ListingIndex::default();
+        let mut errors = vec![];
+
+        // The last listing number used in each (top-level) chapter, so that
+        // listings with an `id` but no `number` pick up where it left off.
+        let mut last_numbers: HashMap<u32, u32> = HashMap::new();
+
+        // Where each listing number was first used, to catch duplicates.
+        let mut seen_numbers: HashMap<String, String> = HashMap::new();
+
+        // The number of the listing being scanned, to note whether its code
+        // compiles.
+        let mut current: Option<String> = None;
+
+        for parsed in chapters {
+            let chapter = parsed.chapter;
+            let info = ChapterInfo::from(chapter);
+            let src = parsed.src();
+
+            for (event, range) in parsed.events.iter().cloned() {
+                let tag = match event {
+                    Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(
+                        info,
+                    ))) if info.contains("does_not_compile") => {
+                        let target = current
+                            .as_ref()
+                            .and_then(|number| index.by_number.get_mut(number));
+                        if let Some(target) = target {
+                            target.does_not_compile = true;
+                        }
+                        continue;
                     }
-                    ("caption", Some(value)) => Ok(builder.with_caption(value)),
-                    ("caption", None) => {
-                        Err(String::from("caption attribute without value"))
+                    Event::Html(tag) if tag.starts_with("</Listing>") => {
+                        current = None;
+                        continue;
                     }
-                    ("file-name", Some(value)) => {
-                        Ok(builder.with_file_name(value))
+                    Event::Html(tag) if is_listing_open(&tag) => tag,
+                    _ => continue,
+                };
+                current = None;
+
+                let diagnostic = |message: String| {
+                    info.diagnostic(src, range.clone(), message)
+                };
+                let mut listing = match parse_attributes(&tag, "Listing")
+                    .and_then(ListingBuilder::from_attributes)
+                {
+                    Ok(builder) => builder.build(),
+                    Err(reason) => {
+                        errors.push(diagnostic(reason));
+                        continue;
                     }
-                    ("file-name", None) => {
-                        Err(String::from("file-name attribute without value"))
+                };
+
+                let id = listing.id.clone();
+                let number = match &listing.number {
+                    Some(number) => {
+                        if let Ok((chapter, n)) = parse_number(number) {
+                            last_numbers.insert(chapter, n);
+                        }
+                        number.clone()
                     }
+                    None => match (&id, info.number) {
+                        (Some(_), Some(chapter)) => {
+                            let n = last_numbers.entry(chapter).or_default();
+                            *n += 1;
+                            format!("{chapter}-{n}")
+                        }
+                        (Some(id), None) => {
+                            errors.push(diagnostic(format!(
+                                "cannot number listing '{id}' in an unnumbered chapter"
+                            )));
+                            continue;
+                        }
+                        (None, _) => continue,
+                    },
+                };
 
-                    _ => Ok(builder), // TODO: error on extra attrs?
+                match seen_numbers.get(&number) {
+                    Some(first) => errors.push(diagnostic(format!(
+                        "duplicate listing number '{number}' (already used at {first})"
+                    ))),
+                    None => {
+                        let location = diagnostic(String::new()).location();
+                        seen_numbers.insert(number.clone(), location);
+                        index.by_number.insert(
+                            number.clone(),
+                            Target {
+                                number: number.clone(),
+                                path: chapter.path.clone(),
+                                does_not_compile: listing.flags.compiles
+                                    == Some(false),
+                            },
+                        );
+                        current = Some(number.clone());
+                    }
                 }
-            })?
-            .build();
 
-        let opening_event = match mode {
-            Mode::Default => {
-                let opening_html = listing.opening_html();
-                Event::Html(opening_html.into())
-            }
-            Mode::Simple => {
-                let opening_text = listing.opening_text();
-                Event::Text(opening_text.into())
+                listing.number = Some(number.clone());
+                index.listings.push(Entry {
+                    listing,
+                    chapter_name: chapter.name.clone(),
+                    path: chapter.path.clone(),
+                });
+
+                let Some(id) = id else {
+                    continue;
+                };
+
+                match index.by_id.get(&id) {
+                    Some(existing) => {
+                        errors.push(diagnostic(format!(
+                            "duplicate listing id '{id}' (already used by Listing {existing})"
+                        )));
+                    }
+                    None => {
+                        index.by_id.insert(id, number);
+                    }
+                }
             }
-        };
+        }
 
-        self.current = Some(listing);
-        self.events.push(Ok(opening_event));
-        Ok(())
+        if errors.is_empty() {
+            Ok(index)
+        } else {
+            Err(errors)
+        }
     }
 
-    fn close_listing(&mut self, tag: pulldown_cmark::CowStr<'_>, mode: Mode) {
-        let trailing = if !tag.ends_with('>') {
-            tag.replace("</Listing>", "")
-        } else {
-            String::from("")
-        };
+    fn get(&self, id: &str) -> Result<&Target, String> {
+        self.by_id
+            .get(id)
+            .and_then(|number| self.by_number.get(number))
+            .ok_or_else(|| format!("Unknown listing id '{id}'"))
+    }
 
-        match &self.current {
-            Some(listing) => {
-                let closing_event = match mode {
-                    Mode::Default => {
-                        let closing_html = listing.closing_html(&trailing);
-                        Event::Html(closing_html.into())
-                    }
-                    Mode::Simple => {
-                        let closing_text = listing.closing_text(&trailing);
-                        Event::Text(closing_text.into())
-                    }
-                };
+    /// Look up the listing an output listing's `for` attribute refers to, by
+    /// either its number or its `id`.
+    fn get_for(&self, reference: &str) -> Result<&Target, String> {
+        if parse_number(reference).is_err() {
+            return self.get(reference);
+        }
 
-                self.current = None;
-                self.events.push(Ok(closing_event));
-            }
-            None => {
-                self.events.push(Err(String::from(
-                    "Closing `</Listing>` without opening tag.",
-                )));
-            }
+        self.by_number.get(reference).ok_or_else(|| {
+            format!("`for` refers to Listing {reference}, which does not exist")
+        })
+    }
+
+    /// Render the Markdown for a chapter at `path`, titled `title`, with a
+    /// table of every numbered listing in the book.
+    ///
+    /// The links point at the chapters' Markdown sources, which the HTML
+    /// renderer rewrites to point to the rendered pages, and the `markdown`
+    /// renderer leaves alone.
+    fn list_of_listings(&self, title: &str, path: &Path) -> String {
+        let root = path_to_root(path);
+        let mut buf = format!(
+            "# {title}\n\n| Listing | Caption | File name | Chapter |\n| ------- | ------- | --------- | ------- |\n"
+        );
+
+        for Entry {
+            listing,
+            chapter_name,
+            path,
+        } in &self.listings
+        {
+            let number = listing.number.as_deref().unwrap_or_default();
+            let caption = listing.caption.as_deref().unwrap_or_default();
+            let file_name = listing.file_name.as_deref().unwrap_or_default();
+            let (listing_link, chapter_link) = match path {
+                Some(path) => {
+                    let path = path.display();
+                    (
+                        format!(
+                            "[Listing {number}]({root}{path}#listing-{number})"
+                        ),
+                        format!("[{chapter_name}]({root}{path})"),
+                    )
+                }
+                None => (format!("Listing {number}"), chapter_name.clone()),
+            };
+
+            buf.push_str(&format!(
+                "| {listing_link} | {} | {} | {} |\n",
+                escape_table_cell(caption),
+                escape_table_cell(file_name),
+                escape_table_cell(&chapter_link),
+            ));
         }
+
+        buf
     }
 }
 
-#[derive(Debug)]
-struct Listing {
-    number: Option<String>,
-    caption: Option<String>,
-    file_name: Option<String>,
+fn escape_table_cell(text: &str) -> String {
+    text.replace('|', "\\|")
 }
 
-impl Listing {
-    fn opening_html(&self) -> String {
-        let figure = String::from("<figure class=\"listing\">\n");
-
-        match self.file_name.as_ref() {
-            Some(file_name) => format!(
-                "{figure}<span class=\"file-name\">Filename: {file_name}</span>\n",
+impl Target {
+    /// The link to this listing from the chapter at `from`.
+    fn href(&self, from: Option<&Path>) -> String {
+        let anchor = format!("#listing-{}", self.number);
+        match (&self.path, from) {
+            (Some(path), Some(from)) if path != from => format!(
+                "{}{}{anchor}",
+                path_to_root(from),
+                path.with_extension("html").display()
             ),
-            None => figure,
+            _ => anchor,
         }
     }
+}
 
-    fn closing_html(&self, trailing: &str) -> String {
-        match (&self.number, &self.caption) {
-            (Some(number), Some(caption)) => format!(
-                r#"<figcaption>Listing {number}: {caption}</figcaption>
-</figure>{trailing}"#
-            ),
-            (None, Some(caption)) => format!(
-                r#"<figcaption>{caption}</figcaption>
-</figure>{trailing}"#
-            ),
-            (Some(number), None) => format!(
-                r#"<figcaption>Listing {number}</figcaption>
-</figure>{trailing}"#
-            ),
-            (None, None) => format!("</figure>{trailing}"),
+/// The chapter currently being rewritten.
+#[derive(Debug, Default, Clone, Copy)]
+struct ChapterInfo<'a> {
+    /// Where the chapter will be rendered, for linking to it.
+    path: Option<&'a Path>,
+    /// Where the chapter came from, for reporting errors.
+    source_path: Option<&'a Path>,
+    /// The number of the top-level chapter this chapter belongs to: `3` for
+    /// both `ch03-00-common-programming-concepts.md` and
+    /// `ch03-05-control-flow.md`.
+    number: Option<u32>,
+    /// The root directory of the book, which `src` attributes are relative to.
+    root: Option<&'a Path>,
+    /// The book's source directory, which `source_path` is relative to.
+    src_dir: Option<&'a Path>,
+}
+
+impl<'a> From<&'a Chapter> for ChapterInfo<'a> {
+    fn from(chapter: &'a Chapter) -> Self {
+        ChapterInfo {
+            path: chapter.path.as_deref(),
+            source_path: chapter.source_path.as_deref(),
+            root: None,
+            src_dir: None,
+            number: chapter
+                .number
+                .as_ref()
+                .and_then(|number| number.first().copied()),
         }
     }
+}
 
-    fn opening_text(&self) -> String {
-        self.file_name
-            .as_ref()
-            .map(|file_name| format!("\nFilename: {file_name}\n"))
-            .unwrap_or_default()
+impl ChapterInfo<'_> {
+    /// Report `message` about the bytes at `range` in the chapter source
+    /// `src`.
+    fn diagnostic(
+        &self,
+        src: &str,
+        range: Range<usize>,
+        message: String,
+    ) -> Diagnostic {
+        Diagnostic::new(
+            src,
+            range,
+            self.source_path.map(Path::to_path_buf),
+            message,
+        )
     }
+}
 
-    fn closing_text(&self, trailing: &str) -> String {
-        match (&self.number, &self.caption) {
-            (Some(number), Some(caption)) => {
-                format!("Listing {number}: {caption}{trailing}")
+/// Parse a listing number like `2-4` into its chapter and listing parts.
+fn parse_number(number: &str) -> Result<(u32, u32), String> {
+    number
+        .split_once('-')
+        .and_then(|(chapter, n)| Some((chapter.parse().ok()?, n.parse().ok()?)))
+        .ok_or_else(|| {
+            format!("invalid listing number '{number}': expected `<chapter>-<n>`, like `2-4`")
+        })
+}
+
+/// Check that `src` follows the naming rules in `ADMIN_TASKS.md`, i.e. that
+/// Listing 2-1 lives in `listings/ch02-<something>/listing-02-01/`.
+fn check_listing_path(src: &str, number: &str) -> Result<(), String> {
+    let (chapter, n) = parse_number(number)?;
+    let mut components = Path::new(src)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy());
+
+    match (components.next(), components.next(), components.next()) {
+        (Some(listings), Some(chapter_dir), Some(listing_dir))
+            if listings == "listings" =>
+        {
+            let chapter_prefix = format!("ch{chapter:02}-");
+            let expected = format!("listing-{chapter:02}-{n:02}");
+            if !chapter_dir.starts_with(&chapter_prefix) {
+                Err(format!(
+                    "`src` '{src}' for listing {number} should be in a `listings/{chapter_prefix}*` directory"
+                ))
+            } else if listing_dir != expected {
+                Err(format!(
+                    "`src` '{src}' for listing {number} should be in a `{expected}` directory, not `{listing_dir}`"
+                ))
+            } else {
+                Ok(())
             }
-            (None, Some(caption)) => format!("{caption}{trailing}"),
-            (Some(number), None) => format!("Listing {number}{trailing}"),
-            (None, None) => trailing.into(),
         }
+        _ => Err(format!(
+            "`src` '{src}' for listing {number} should be in the `listings` directory"
+        )),
     }
 }
 
-struct ListingBuilder {
-    number: Option<String>,
-    caption: Option<String>,
-    file_name: Option<String>,
+/// The code block language to use for a file, based on its extension.
+fn language_for(path: &Path) -> &str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("rs") => "rust",
+        Some("txt") => "text",
+        Some("md") => "markdown",
+        Some(other) => other,
+        None => "text",
+    }
 }
 
-impl ListingBuilder {
-    fn new() -> ListingBuilder {
-        ListingBuilder {
-            number: None,
-            caption: None,
-            file_name: None,
-        }
-    }
+fn is_listing_open(tag: &str) -> bool {
+    tag.strip_prefix("<Listing").is_some_and(|rest| {
+        rest.starts_with(|c: char| c.is_whitespace() || c == '>')
+    })
+}
 
-    fn with_number(mut self, value: String) -> Self {
-        self.number = Some(value);
-        self
-    }
+fn is_file_open(tag: &str) -> bool {
+    tag.strip_prefix("<File").is_some_and(|rest| {
+        rest.starts_with(|c: char| c.is_whitespace() || c == '>')
+    })
+}
 
-    fn with_caption(mut self, value: String) -> Self {
-        self.caption = Some(value);
-        self
-    }
+fn is_listing_ref(tag: &str) -> bool {
+    tag.strip_prefix("<ListingRef").is_some_and(|rest| {
+        rest.starts_with(|c: char| c.is_whitespace() || c == '/')
+    })
+}
 
-    fn with_file_name(mut self, value: String) -> Self {
-        self.file_name = Some(value);
-        self
-    }
+type Attributes = Vec<(String, Option<String>)>;
 
-    fn build(self) -> Listing {
-        let caption = self.caption.map(|caption_source| {
-            let events = new_cmark_parser(&caption_source, true);
-            let mut buf = String::with_capacity(caption_source.len() * 2);
-            html::push_html(&mut buf, events);
-
-            // This is not particularly principled, but since the only
-            // place it is used is here, for caption source handling, it
-            // is “fine”.
-            buf.replace("<p>", "").replace("</p>", "").replace('\n', "")
-        });
+fn parse_attributes(tag: &str, element: &str) -> Result<Attributes, String> {
+    // We do not *keep* the version constructed here, just temporarily
+    // construct it so the HTML parser, which expects properly closed tags
+    // to parse it as a *tag* rather than a *weird text node*, which accept
+    // it and provide a useful view of it.
+    let to_parse = if tag.trim_end().ends_with("/>") {
+        tag.to_string()
+    } else {
+        format!("{tag}</{element}>")
+    };
+
+    let attributes = Dom::parse(&to_parse)
+        .map_err(|e| e.to_string())?
+        .children
+        .into_iter()
+        .filter_map(|node| match node {
+            html_parser::Node::Element(element) => Some(
+                element
+                    .id
+                    .map(|id| (String::from("id"), Some(id)))
+                    .into_iter()
+                    .chain(element.attributes),
+            ),
+            html_parser::Node::Text(_) | html_parser::Node::Comment(_) => None,
+        })
+        .flatten()
+        .collect();
+
+    Ok(attributes)
+}
+
+fn attribute<'a>(attributes: &'a Attributes, key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(k, _)| k == key)
+        .and_then(|(_, value)| value.as_deref())
+}
 
+/// The edits which rewrite the listings in `src`, which has `events`.
+fn listing_edits(
+    src: &str,
+    events: &[(Event<'_>, Range<usize>)],
+    config: Config,
+    index: &ListingIndex,
+    chapter: ChapterInfo<'_>,
+) -> Result<Vec<Edit>, Vec<Diagnostic>> {
+    let mut final_state = events.iter().cloned().fold(
+        ListingState {
+            current: None,
+            opened_at: 0..0,
+            unopened: 0,
+            in_file: false,
+            code: None,
+            edits: vec![],
+        },
+        |mut state, (ev, range)| {
+            let locate = |reason| chapter.diagnostic(src, range.clone(), reason);
+            // Only the tag or code block itself gets replaced: the line ending
+            // after it stays where it is.
+            let span = trim_end(src, range.clone());
+            match ev {
+                Event::Html(tag) | Event::InlineHtml(tag)
+                    if is_listing_ref(&tag) =>
+                {
+                    match state.reference(tag, config.mode, index, chapter.path)
+                    {
+                        Ok(text) => state.replace(span, text),
+                        Err(reason) => state.edits.push(Err(locate(reason))),
+                    }
+                }
+                // Listing tags only start an HTML block on a line of their
+                // own, so anything else on the line leaves them in a
+                // paragraph. They are still handled, so that this is the only
+                // error reported for them.
+                Event::InlineHtml(tag)
+                    if is_listing_open(&tag) || tag.starts_with("</Listing>") =>
+                {
+                    if is_listing_open(&tag) {
+                        state.opened_at = range.clone();
+                        let opened = state.open_listing(
+                            tag.clone(),
+                            span,
+                            config,
+                            index,
+                            chapter,
+                        );
+                        if opened.is_err() {
+                            state.unopened += 1;
+                        }
+                    } else if state.unopened > 0 {
+                        state.unopened -= 1;
+                    } else {
+                        let _ = state.close_listing(span, config);
+                    }
+                    state.edits.push(Err(locate(format!(
+                        "`{}` must be on a line of its own",
+                        tag.trim()
+                    ))));
+                }
+                Event::Html(tag) => {
+                    if is_listing_open(&tag) {
+                        match state
+                            .open_listing(tag, span, config, index, chapter)
+                        {
+                            Ok(()) => state.opened_at = range.clone(),
+                            Err(reason) => {
+                                state.unopened += 1;
+                                state.edits.push(Err(locate(reason)));
+                            }
+                        }
+                    } else if tag.starts_with("</Listing>") {
+                        // The listing this closes was already reported.
+                        if state.unopened > 0 {
+                            state.unopened -= 1;
+                        } else if let Err(reason) =
+                            state.close_listing(span, config)
+                        {
+                            state.edits.push(Err(locate(reason)));
+                        }
+                    } else if is_file_open(&tag) {
+                        if let Err(reason) =
+                            state.open_file(tag, span, config.mode)
+                        {
+                            state.edits.push(Err(locate(reason)));
+                        }
+                    } else if tag.starts_with("</File>") {
+                        if let Err(reason) = state.close_file(span, config.mode)
+                        {
+                            state.edits.push(Err(locate(reason)));
+                        }
+                    }
+                }
+                Event::Start(Tag::CodeBlock(_))
+                    if state
+                        .current
+                        .as_ref()
+                        .is_some_and(|listing| listing.src.is_some()) =>
+                {
+                    state.edits.push(Err(locate(String::from(
+                        "a listing with a `src` attribute cannot also contain a code block",
+                    ))));
+                }
+                Event::Start(Tag::CodeBlock(kind))
+                    if config.style_lines
+                        && state
+                            .current
+                            .as_ref()
+                            .is_some_and(Listing::renders_lines) =>
+                {
+                    let info = match kind {
+                        CodeBlockKind::Fenced(_) => {
+                            let info = &src[info_span(src, span.clone())];
+                            let flags = state.current.as_ref().map(|l| l.flags);
+                            match flags.unwrap_or_default().apply(info) {
+                                Ok(info) => info,
+                                Err(reason) => {
+                                    state.edits.push(Err(locate(reason)));
+                                    info.to_string()
+                                }
+                            }
+                        }
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    state.code = Some(CodeBlock {
+                        span,
+                        info,
+                        code: String::new(),
+                    });
+                }
+                Event::Start(Tag::CodeBlock(kind))
+                    if state
+                        .current
+                        .as_ref()
+                        .is_some_and(|listing| !listing.flags.is_empty()) =>
+                {
+                    let flags = state.current.as_ref().map(|l| l.flags);
+                    let flags = flags.unwrap_or_default();
+                    match kind {
+                        CodeBlockKind::Fenced(_) => {
+                            let info_span = info_span(src, span);
+                            match flags.apply(&src[info_span.clone()]) {
+                                Ok(info) => state.replace(info_span, info),
+                                Err(reason) => {
+                                    state.edits.push(Err(locate(reason)))
+                                }
+                            }
+                        }
+                        CodeBlockKind::Indented => {
+                            state.edits.push(Err(locate(String::from(
+                                "`compiles`, `panics`, and `playground` need a fenced code block to add flags to",
+                            ))));
+                        }
+                    }
+                }
+                Event::Text(text) if state.code.is_some() => {
+                    if let Some(CodeBlock { code, .. }) = &mut state.code {
+                        code.push_str(&text);
+                    }
+                }
+                Event::End(TagEnd::CodeBlock) if state.code.is_some() => {
+                    if let (Some(CodeBlock { span, info, code }), Some(listing)) =
+                        (state.code.take(), &state.current)
+                    {
+                        match listing.render_included_code(
+                            &info,
+                            &code,
+                            chapter,
+                            config,
+                        ) {
+                            Ok(rendered) => state.replace(span, rendered),
+                            Err(reason) => {
+                                state.edits.push(Err(locate(reason)))
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            };
+            state
+        },
+    );
+
+    if final_state.current.is_some() {
+        let opened_at = final_state.opened_at.clone();
+        final_state.edits.push(Err(chapter.diagnostic(
+            src,
+            opened_at,
+            String::from("Unclosed listing"),
+        )));
+    }
+
+    let (edits, errors): (Vec<_>, Vec<_>) =
+        final_state.edits.into_iter().partition(|e| e.is_ok());
+
+    if !errors.is_empty() {
+        return Err(errors.into_iter().map(|e| e.unwrap_err()).collect());
+    }
+
+    Ok(edits.into_iter().map(|ok| ok.unwrap()).collect())
+}
+
+/// Where the info string of the fenced code block at `span` in `src` is: just
+/// after the fence, and empty, if the code block does not have one.
+fn info_span(src: &str, span: Range<usize>) -> Range<usize> {
+    let block = &src[span.clone()];
+    let first_line = block.lines().next().unwrap_or_default();
+    let fence_start = first_line.find(['`', '~']).unwrap_or_default();
+    let fence = &first_line[fence_start..];
+    let fence_char = fence.chars().next().unwrap_or('`');
+    let after_fence =
+        fence_start + fence.find(|c| c != fence_char).unwrap_or(fence.len());
+
+    let rest = &first_line[after_fence..];
+    let start = after_fence + (rest.len() - rest.trim_start().len());
+    let end = after_fence + rest.trim_end().len();
+    span.start + start..span.start + end.max(start)
+}
+
+/// A code block in a listing which highlights, dims, or numbers its lines,
+/// which is collected so it can be rendered line by line.
+struct CodeBlock {
+    span: Range<usize>,
+    info: String,
+    code: String,
+}
+
+struct ListingState {
+    current: Option<Listing>,
+    /// Where the current listing's opening tag is, for reporting it if it is
+    /// never closed.
+    opened_at: Range<usize>,
+    /// How many listings which could not be opened, and so were reported
+    /// already, have not been closed yet.
+    unopened: usize,
+    /// Whether the parser is inside a `<File>` in the current listing.
+    in_file: bool,
+    /// The code block being collected, if any.
+    code: Option<CodeBlock>,
+    edits: Vec<Result<Edit, Diagnostic>>,
+}
+
+impl ListingState {
+    /// Replace `span` with `text`. Line endings around `text` are dropped,
+    /// since the lines `span` is on are kept.
+    fn replace(&mut self, span: Range<usize>, text: String) {
+        let text = text.trim_matches('\n').to_string();
+        self.edits.push(Ok(Edit { span, text }));
+    }
+
+    fn open_listing(
+        &mut self,
+        tag: pulldown_cmark::CowStr<'_>,
+        span: Range<usize>,
+        config: Config,
+        index: &ListingIndex,
+        chapter: ChapterInfo<'_>,
+    ) -> Result<(), String> {
+        if self.current.is_some() {
+            return Err(String::from(
+                "`<Listing>` inside another listing; close that one with `</Listing>` first",
+            ));
+        }
+
+        let mut listing = ListingBuilder::from_attributes(parse_attributes(
+            &tag, "Listing",
+        )?)?
+        .build();
+
+        match (&listing.number, &listing.id) {
+            (Some(number), _) => {
+                let (listing_chapter, _) = parse_number(number)?;
+                if let Some(chapter_number) = chapter.number {
+                    if listing_chapter != chapter_number {
+                        return Err(format!(
+                            "listing number '{number}' does not match chapter {chapter_number}"
+                        ));
+                    }
+                }
+            }
+            (None, Some(id)) => {
+                listing.number = Some(index.get(id)?.number.clone());
+            }
+            (None, None) => {}
+        }
+
+        if let Some(reference) = &listing.output_for {
+            let target = index.get_for(reference)?;
+            listing.output_of = Some(OutputOf {
+                number: target.number.clone(),
+                href: target.href(chapter.path),
+                does_not_compile: target.does_not_compile,
+            });
+        }
+
+        let code_block = match &listing.src {
+            Some(src) => {
+                let (language, code, lines) =
+                    listing.code(src, chapter.root)?;
+                Some(listing.render_code(
+                    &listing.flags.apply(language)?,
+                    &code,
+                    Some(&lines),
+                    config,
+                )?)
+            }
+            None => None,
+        };
+
+        let opening = match config.mode {
+            Mode::Default => listing.opening_html(),
+            Mode::Simple => listing.opening_text(),
+            Mode::Print => listing.opening_print(),
+        };
+        self.current = Some(listing);
+        match code_block {
+            // The line ending after the tag is kept, so one more puts a blank
+            // line between the code block and whatever closes the listing.
+            Some(code_block) => {
+                let opening =
+                    format!("{}\n\n{}", opening.trim_end(), code_block.trim());
+                let text = format!("{}\n", opening.trim_matches('\n'));
+                self.edits.push(Ok(Edit { span, text }));
+            }
+            None => self.replace(span, opening),
+        }
+        Ok(())
+    }
+
+    fn reference(
+        &self,
+        tag: pulldown_cmark::CowStr<'_>,
+        mode: Mode,
+        index: &ListingIndex,
+        path: Option<&Path>,
+    ) -> Result<String, String> {
+        let attributes = parse_attributes(&tag, "ListingRef")?;
+        let id = attribute(&attributes, "id").ok_or_else(|| {
+            String::from("ListingRef without an id attribute")
+        })?;
+
+        index.get(id).map(|target| {
+            let number = &target.number;
+            match mode {
+                Mode::Default => {
+                    let href = target.href(path);
+                    format!(r#"<a href="{href}">Listing {number}</a>"#)
+                }
+                Mode::Simple => format!("Listing {number}"),
+                Mode::Print => format!("[Listing {number}](#listing-{number})"),
+            }
+        })
+    }
+
+    /// Start one of the files in a listing which shows several of them.
+    fn open_file(
+        &mut self,
+        tag: pulldown_cmark::CowStr<'_>,
+        span: Range<usize>,
+        mode: Mode,
+    ) -> Result<(), String> {
+        let Some(listing) = &self.current else {
+            return Err(String::from("`<File>` outside of a `<Listing>`"));
+        };
+        if listing.file_name.is_some() || listing.src.is_some() {
+            return Err(String::from(
+                "a listing with a `file-name` or `src` attribute cannot also contain `<File>` elements",
+            ));
+        }
+        if self.in_file {
+            return Err(String::from("`<File>` inside another `<File>`"));
+        }
+
+        let mut name = None;
+        for (key, value) in parse_attributes(&tag, "File")? {
+            match (key.as_str(), value) {
+                ("name", Some(value)) => name = Some(value),
+                ("name", None) => {
+                    return Err(String::from("name attribute without value"))
+                }
+                (other, _) => {
+                    return Err(format!(
+                        "unknown attribute '{other}' on <File>: expected `name`"
+                    ))
+                }
+            }
+        }
+        let name = name
+            .ok_or_else(|| String::from("<File> without a name attribute"))?;
+
+        let opening = match mode {
+            Mode::Default => format!(
+                "<div class=\"listing-file\">\n<span class=\"file-name\">Filename: {name}</span>"
+            ),
+            Mode::Simple => format!("Filename: {name}"),
+            Mode::Print => {
+                format!("::: {{.file name={}}}", quote_attribute(&name))
+            }
+        };
+
+        self.in_file = true;
+        self.replace(span, opening);
+        Ok(())
+    }
+
+    fn close_file(
+        &mut self,
+        span: Range<usize>,
+        mode: Mode,
+    ) -> Result<(), String> {
+        if !self.in_file {
+            return Err(String::from("Closing `</File>` without opening tag."));
+        }
+
+        let closing = match mode {
+            Mode::Default => String::from("</div>"),
+            // There is nothing to close, and the next file's name (or the
+            // caption) is enough to separate them.
+            Mode::Simple => String::new(),
+            Mode::Print => String::from(":::"),
+        };
+
+        self.in_file = false;
+        self.replace(span, closing);
+        Ok(())
+    }
+
+    fn close_listing(
+        &mut self,
+        span: Range<usize>,
+        config: Config,
+    ) -> Result<(), String> {
+        match &self.current {
+            Some(listing) => {
+                let closing = match config.mode {
+                    Mode::Default => listing.closing_html(config.permalinks),
+                    Mode::Simple => listing.closing_text(),
+                    Mode::Print => listing.closing_print(),
+                };
+
+                self.current = None;
+                self.replace(span, closing);
+
+                // The listing itself is still closed, so that this is the
+                // only error reported for it.
+                if std::mem::take(&mut self.in_file) {
+                    Err(String::from("Unclosed `<File>` in listing"))
+                } else {
+                    Ok(())
+                }
+            }
+            None => {
+                Err(String::from("Closing `</Listing>` without opening tag."))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Listing {
+    id: Option<String>,
+    number: Option<String>,
+    caption: Option<String>,
+    file_name: Option<String>,
+    src: Option<String>,
+    anchor: Option<String>,
+    highlight: Option<String>,
+    dim: Option<String>,
+    line_numbers: Option<LineNumbers>,
+    kind: Kind,
+    /// The `for` attribute of an output listing, as written.
+    output_for: Option<String>,
+    /// The listing `output_for` refers to, once it has been looked up.
+    output_of: Option<OutputOf>,
+    flags: Flags,
+}
+
+/// What a listing says about its code, from the `compiles`, `panics`, and
+/// `playground` attributes, which stand in for the flags in its code block's
+/// info string.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Flags {
+    compiles: Option<bool>,
+    panics: Option<bool>,
+    playground: Option<bool>,
+}
+
+/// An attribute as written, the info string flags it calls for, and the ones
+/// which contradict it.
+type Requirement = (
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+);
+
+impl Flags {
+    fn is_empty(&self) -> bool {
+        *self == Flags::default()
+    }
+
+    fn requirements(&self) -> Vec<Requirement> {
+        let mut requirements: Vec<Requirement> = vec![];
+        match self.compiles {
+            Some(true) => requirements.push((
+                "compiles=\"yes\"",
+                &[],
+                &["does_not_compile", "compile_fail"],
+            )),
+            Some(false) => requirements.push((
+                "compiles=\"no\"",
+                &["ignore", "does_not_compile"],
+                &[],
+            )),
+            None => {}
+        }
+        match self.panics {
+            Some(true) => requirements.push((
+                "panics=\"yes\"",
+                &["should_panic", "panics"],
+                &[],
+            )),
+            Some(false) => requirements.push((
+                "panics=\"no\"",
+                &[],
+                &["should_panic", "panics"],
+            )),
+            None => {}
+        }
+        match self.playground {
+            Some(true) => requirements.push((
+                "playground=\"true\"",
+                &[],
+                &["noplayground"],
+            )),
+            Some(false) => requirements.push((
+                "playground=\"false\"",
+                &["noplayground"],
+                &[],
+            )),
+            None => {}
+        }
+        requirements
+    }
+
+    /// Add whichever flags `info` is missing to the end of it, or explain how
+    /// it contradicts the attributes. Every flag is one of rustdoc's, so a
+    /// code block without a language gets `rust` first: otherwise the first
+    /// flag would become its language.
+    ///
+    /// The flags are separated the same way as the words already in `info`:
+    /// with spaces if it only has spaces between them, or else with commas.
+    fn apply(&self, info: &str) -> Result<String, String> {
+        let words: Vec<&str> = info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let separator = if words.len() > 1 && !info.contains(',') {
+            ' '
+        } else {
+            ','
+        };
+
+        let mut info = info.to_string();
+        for (attribute, wanted, contradicting) in self.requirements() {
+            if let Some(flag) =
+                contradicting.iter().find(|flag| words.contains(flag))
+            {
+                return Err(format!(
+                    "`{attribute}` conflicts with `{flag}` in the code block's info string"
+                ));
+            }
+            for flag in wanted.iter().filter(|flag| !words.contains(flag)) {
+                if info.trim().is_empty() {
+                    info = String::from("rust");
+                }
+                info.push(separator);
+                info.push_str(flag);
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// Parse an attribute like `compiles="no"`, which is either `yes` or `no`.
+fn parse_flag(
+    name: &str,
+    value: &str,
+    yes: &str,
+    no: &str,
+) -> Result<bool, String> {
+    if value == yes {
+        Ok(true)
+    } else if value == no {
+        Ok(false)
+    } else {
+        Err(format!(
+            "invalid `{name}` '{value}': expected `{yes}` or `{no}`"
+        ))
+    }
+}
+
+/// What a listing shows, from the `kind` attribute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Code,
+    /// The output of running some code, usually from its `output.txt`.
+    Output,
+}
+
+impl TryFrom<&str> for Kind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "code" => Ok(Kind::Code),
+            "output" => Ok(Kind::Output),
+            other => Err(format!(
+                "invalid `kind` '{other}': expected `code` or `output`"
+            )),
+        }
+    }
+}
+
+/// The listing an output listing shows the output of.
+#[derive(Debug)]
+struct OutputOf {
+    number: String,
+    /// The link to the listing from the output listing's chapter.
+    href: String,
+    does_not_compile: bool,
+}
+
+impl Listing {
+    /// Read the code for a listing with a `src` attribute from the file it
+    /// names in the book `root`, along with the language for its code block
+    /// and the line in the file each line of code came from.
+    fn code<'s>(
+        &self,
+        src: &'s str,
+        root: Option<&Path>,
+    ) -> Result<(&'s str, String, Vec<usize>), String> {
+        if let Some(number) = &self.number {
+            check_listing_path(src, number)?;
+        }
+
+        let root = root.ok_or_else(|| {
+            format!("cannot read `src` '{src}' without knowing the book root")
+        })?;
+        let path = Path::new(src);
+        let contents = fs::read_to_string(root.join(path))
+            .map_err(|e| format!("could not read `src` '{src}': {e}"))?;
+
+        let language = language_for(path);
+        let (code, lines) = include_lines(
+            &contents,
+            self.anchor.as_deref(),
+            language == "rust",
+        );
+        Ok((language, code, lines))
+    }
+
+    /// Whether the listing needs its code split into lines, to style or
+    /// number them.
+    fn renders_lines(&self) -> bool {
+        self.highlight.is_some()
+            || self.dim.is_some()
+            || self.line_numbers.is_some()
+    }
+
+    /// Render a code block written in the chapter itself, first resolving it
+    /// if it is nothing but an `{{#include}}` or `{{#rustdoc_include}}`, so
+    /// that `line-numbers="source"` knows where its lines came from.
+    ///
+    /// mdBook's `links` preprocessor normally expands those before this one
+    /// runs; they only get here if `trpl-listing` is configured to run
+    /// `before = ["links"]`.
+    fn render_included_code(
+        &self,
+        info: &str,
+        code: &str,
+        chapter: ChapterInfo<'_>,
+        config: Config,
+    ) -> Result<String, String> {
+        let Some(include) = Include::parse(code) else {
+            return self.render_code(info, code, None, config);
+        };
+
+        let include = include?;
+        let dir = match (chapter.src_dir, chapter.source_path) {
+            (Some(src_dir), Some(source_path)) => {
+                src_dir.join(source_path.parent().unwrap_or(Path::new("")))
+            }
+            _ => {
+                return Err(format!(
+                    "cannot read '{}' without knowing where the chapter is",
+                    include.path
+                ))
+            }
+        };
+        let contents = fs::read_to_string(dir.join(include.path))
+            .map_err(|e| format!("could not read '{}': {e}", include.path))?;
+
+        let (code, lines) =
+            include_lines(&contents, include.anchor, include.rustdoc);
+        self.render_code(info, &code, Some(&lines), config)
+    }
+
+    /// Render a code block in the listing, applying any `highlight`, `dim`,
+    /// and `line-numbers` attributes, unless `config` says not to style lines.
+    /// `source_lines` is the line in its file each line of `code` came from, if
+    /// it came from a file.
+    ///
+    /// This is emitted as raw Markdown (or HTML) rather than as code block
+    /// events, because it ends up inside the HTML block for the `<Listing>`
+    /// tag, where a code block would not be rendered as such.
+    fn render_code(
+        &self,
+        info: &str,
+        code: &str,
+        source_lines: Option<&[usize]>,
+        config: Config,
+    ) -> Result<String, String> {
+        if !config.style_lines || !self.renders_lines() {
+            return Ok(fenced_code_block(info, code));
+        }
+
+        let language = info.split([' ', ',']).next().unwrap_or_default();
+        let lines = self.style_lines(language, code, source_lines)?;
+        let markers = self.highlight.is_some() || self.dim.is_some();
+        match config.mode {
+            Mode::Default => Ok(lines_html(info, &lines)),
+            Mode::Simple => Ok(lines_with_markers(info, &lines, markers)),
+            // The `highlight` and `dim` attributes on the div carry the line
+            // styles, so the code itself is left alone, other than telling
+            // Pandoc where to start numbering it.
+            Mode::Print => {
+                let first = lines.iter().find_map(|line| line.number);
+                let info = match first {
+                    Some(first) => format!(
+                        "{{.{language} .numberLines startFrom=\"{first}\"}}"
+                    ),
+                    None => info.to_string(),
+                };
+                Ok(fenced_code_block(&info, code))
+            }
+        }
+    }
+
+    /// Split `code` into lines, and work out how each one should be styled.
+    ///
+    /// Line numbers in `highlight` and `dim` count only the lines a reader
+    /// sees, so for Rust they skip the hidden `# ` lines, including the ones
+    /// `{{#rustdoc_include}}` (or `src` with an `anchor`) adds for everything
+    /// outside the anchor.
+    fn style_lines<'c>(
+        &self,
+        language: &str,
+        code: &'c str,
+        source_lines: Option<&[usize]>,
+    ) -> Result<Vec<Line<'c>>, String> {
+        let highlight =
+            parse_line_ranges("highlight", self.highlight.as_deref())?;
+        let dim = parse_line_ranges("dim", self.dim.as_deref())?;
+        if matches!(self.line_numbers, Some(LineNumbers::Source))
+            && source_lines.is_none()
+        {
+            return Err(String::from(
+                "`line-numbers=\"source\"` needs code from a file, through `src` or `{{#rustdoc_include}}`",
+            ));
+        }
+
+        let mut lines = vec![];
+        let mut visible = 0;
+        for (index, text) in code.lines().enumerate() {
+            let hidden = language == "rust" && is_hidden_line(text);
+            let style = if hidden {
+                LineStyle::Normal
+            } else {
+                visible += 1;
+                let highlighted =
+                    highlight.iter().any(|r| r.contains(&visible));
+                let dimmed = dim.iter().any(|r| r.contains(&visible));
+                match (highlighted, dimmed) {
+                    (true, true) => {
+                        return Err(format!(
+                            "line {visible} is both highlighted and dimmed"
+                        ))
+                    }
+                    _ if text.contains("--snip--") => LineStyle::Snip,
+                    (true, false) => LineStyle::Highlight,
+                    (false, true) => LineStyle::Dim,
+                    (false, false) => LineStyle::Normal,
+                }
+            };
+            let number = match (&self.line_numbers, hidden) {
+                (_, true) | (None, _) => None,
+                (Some(LineNumbers::From(start)), false) => {
+                    Some(start + visible - 1)
+                }
+                (Some(LineNumbers::Source), false) => {
+                    source_lines.and_then(|lines| lines.get(index).copied())
+                }
+            };
+            lines.push(Line {
+                text,
+                hidden,
+                style,
+                number,
+            });
+        }
+
+        let ranges = [("highlight", &highlight), ("dim", &dim)];
+        for (attribute, ranges) in ranges {
+            let last =
+                ranges.iter().map(|r| *r.end()).max().unwrap_or_default();
+            if last > visible {
+                return Err(format!(
+                    "`{attribute}` refers to line {last}, but the code block only has {visible} lines"
+                ));
+            }
+        }
+
+        Ok(lines)
+    }
+
+    fn opening_html(&self) -> String {
+        let class = match self.kind {
+            Kind::Code => "listing",
+            Kind::Output => "listing output",
+        };
+        let figure = match &self.number {
+            Some(number) => {
+                format!("<figure class=\"{class}\" id=\"listing-{number}\">\n")
+            }
+            None => format!("<figure class=\"{class}\">\n"),
+        };
+
+        let figure = match self.file_name.as_ref() {
+            Some(file_name) => format!(
+                "{figure}<span class=\"file-name\">Filename: {file_name}</span>\n",
+            ),
+            None => figure,
+        };
+
+        if self.is_collapsible() {
+            format!("{figure}<details>\n<summary>Compiler errors</summary>\n")
+        } else {
+            figure
+        }
+    }
+
+    /// Whether this is the output of code which does not compile, which is
+    /// usually long enough to be worth hiding until someone wants to read it.
+    fn is_collapsible(&self) -> bool {
+        self.output_of
+            .as_ref()
+            .is_some_and(|output_of| output_of.does_not_compile)
+    }
+
+    fn closing_html(&self, permalink: bool) -> String {
+        let label = self.number.as_ref().map(|number| {
+            if permalink {
+                format!(r##"<a href="#listing-{number}">Listing {number}</a>"##)
+            } else {
+                format!("Listing {number}")
+            }
+        });
+
+        let details = if self.is_collapsible() {
+            "</details>\n"
+        } else {
+            ""
+        };
+
+        match (label, self.caption_html()) {
+            (Some(label), Some(caption)) => format!(
+                r#"{details}<figcaption>{label}: {caption}</figcaption>
+</figure>"#
+            ),
+            (None, Some(caption)) => format!(
+                r#"{details}<figcaption>{caption}</figcaption>
+</figure>"#
+            ),
+            (Some(label), None) => format!(
+                r#"{details}<figcaption>{label}</figcaption>
+</figure>"#
+            ),
+            (None, None) => format!("{details}</figure>"),
+        }
+    }
+
+    fn opening_text(&self) -> String {
+        self.file_name
+            .as_ref()
+            .map(|file_name| format!("Filename: {file_name}"))
+            .unwrap_or_default()
+    }
+
+    fn closing_text(&self) -> String {
+        match (&self.number, self.caption_markdown(false)) {
+            (Some(number), Some(caption)) => {
+                format!("Listing {number}: {caption}")
+            }
+            (None, Some(caption)) => caption,
+            (Some(number), None) => format!("Listing {number}"),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The opening of a Pandoc fenced div carrying the listing's metadata as
+    /// attributes, for print pipelines to typeset however they like.
+    fn opening_print(&self) -> String {
+        let mut attributes = vec![];
+        if let Some(number) = &self.number {
+            attributes.push(format!("#listing-{number}"));
+        }
+        attributes.push(String::from(".listing"));
+        if self.kind == Kind::Output {
+            attributes.push(String::from(".output"));
+        }
+        if let Some(number) = &self.number {
+            attributes.push(format!("number={}", quote_attribute(number)));
+        }
+        if let Some(output_of) = &self.output_of {
+            let number = &output_of.number;
+            attributes.push(format!("for={}", quote_attribute(number)));
+        }
+        if let Some(file_name) = &self.file_name {
+            attributes.push(format!("file={}", quote_attribute(file_name)));
+        }
+        if let Some(highlight) = &self.highlight {
+            attributes
+                .push(format!("highlight={}", quote_attribute(highlight)));
+        }
+        if let Some(dim) = &self.dim {
+            attributes.push(format!("dim={}", quote_attribute(dim)));
+        }
+
+        format!(":::: {{{}}}\n", attributes.join(" "))
+    }
+
+    /// The caption, if any, as a nested `caption` div, with its Markdown left
+    /// as written, followed by the end of the listing div.
+    fn closing_print(&self) -> String {
+        match self.caption_markdown(true) {
+            Some(caption) => format!("::: caption\n{caption}\n:::\n::::"),
+            None => String::from("::::"),
+        }
+    }
+
+    /// The caption rendered to inline HTML, starting with which listing this
+    /// is the output of, if it is one.
+    fn caption_html(&self) -> Option<String> {
+        let caption = self.caption.as_deref().map(inline_html);
+        let Some(OutputOf { number, href, .. }) = &self.output_of else {
+            return caption;
+        };
+
+        let output_of =
+            format!(r#"Output of <a href="{href}">Listing {number}</a>"#);
+        match caption {
+            Some(caption) => Some(format!("{output_of}: {caption}")),
+            None => Some(output_of),
+        }
+    }
+
+    /// The caption as Markdown, starting with which listing this is the output
+    /// of, if it is one, which is a link to it if `link` is set.
+    fn caption_markdown(&self, link: bool) -> Option<String> {
+        let Some(OutputOf { number, .. }) = &self.output_of else {
+            return self.caption.clone();
+        };
+
+        let output_of = if link {
+            format!("Output of [Listing {number}](#listing-{number})")
+        } else {
+            format!("Output of Listing {number}")
+        };
+        match &self.caption {
+            Some(caption) => Some(format!("{output_of}: {caption}")),
+            None => Some(output_of),
+        }
+    }
+}
+
+/// A line of code in a listing with `highlight` or `dim` attributes.
+struct Line<'c> {
+    text: &'c str,
+    /// Whether mdBook hides the line by default, like `# fn main() {`.
+    hidden: bool,
+    style: LineStyle,
+    /// The number to show in the gutter, with `line-numbers`.
+    number: Option<usize>,
+}
+
+/// Where to start numbering lines, from the `line-numbers` attribute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineNumbers {
+    /// A bare `line-numbers`, or a number like `line-numbers="7"`.
+    From(usize),
+    /// `line-numbers="source"`: the lines' numbers in the file they came from.
+    Source,
+}
+
+impl TryFrom<Option<&str>> for LineNumbers {
+    type Error = String;
+
+    fn try_from(value: Option<&str>) -> Result<Self, Self::Error> {
+        match value {
+            None => Ok(LineNumbers::From(1)),
+            Some("source") => Ok(LineNumbers::Source),
+            Some(value) => match value.parse() {
+                Ok(start) if start > 0 => Ok(LineNumbers::From(start)),
+                _ => Err(format!(
+                    "invalid `line-numbers` value '{value}': expected a line number or `source`"
+                )),
+            },
+        }
+    }
+}
+
+/// A code block which is nothing but an `{{#include}}` or
+/// `{{#rustdoc_include}}`.
+struct Include<'c> {
+    rustdoc: bool,
+    path: &'c str,
+    anchor: Option<&'c str>,
+}
+
+impl<'c> Include<'c> {
+    fn parse(code: &'c str) -> Option<Result<Include<'c>, String>> {
+        let inner = code.trim().strip_prefix("{{#")?.strip_suffix("}}")?;
+        let (rustdoc, target) = match inner.split_once(' ')? {
+            ("rustdoc_include", target) => (true, target.trim()),
+            ("include", target) => (false, target.trim()),
+            _ => return None,
+        };
+
+        let (path, anchor) = match target.split_once(':') {
+            Some((path, anchor)) => (path, Some(anchor)),
+            None => (target, None),
+        };
+        if anchor.is_some_and(|anchor| {
+            anchor.chars().all(|c| c.is_ascii_digit() || c == ':')
+        }) {
+            return Some(Err(format!(
+                "line ranges like '{target}' are not supported in listings with `highlight`, `dim`, or `line-numbers`; use an anchor instead"
+            )));
+        }
+
+        Some(Ok(Include {
+            rustdoc,
+            path,
+            anchor,
+        }))
+    }
+}
+
+/// Take the lines of `contents` an include with `anchor` would, along with
+/// the 1-based line in `contents` each of them came from.
+///
+/// The anchor comments themselves are left out, so the lines are not
+/// necessarily contiguous. With `rustdoc`, lines outside the anchor are kept
+/// as hidden lines, like `{{#rustdoc_include}}` does.
+fn include_lines(
+    contents: &str,
+    anchor: Option<&str>,
+    rustdoc: bool,
+) -> (String, Vec<usize>) {
+    let code = match (rustdoc, anchor) {
+        (true, Some(anchor)) => {
+            take_rustdoc_include_anchored_lines(contents, anchor)
+        }
+        (true, None) => take_rustdoc_include_lines(contents, ..),
+        (false, Some(anchor)) => take_anchored_lines(contents, anchor),
+        (false, None) => contents.to_string(),
+    };
+
+    // Every line mdBook keeps is either an original line or a hidden copy of
+    // one, in the same order, so each can be matched to the next original
+    // line it is equal to.
+    let mut original = contents.lines().enumerate();
+    let lines = code
+        .lines()
+        .map(|line| {
+            original
+                .by_ref()
+                .find(|(_, source)| {
+                    line == *source || line.strip_prefix("# ") == Some(source)
+                })
+                .map_or(0, |(n, _)| n + 1)
+        })
+        .collect();
+
+    (code, lines)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineStyle {
+    Normal,
+    Highlight,
+    Dim,
+    /// A `// --snip--` line, standing in for code left out of the listing.
+    Snip,
+}
+
+/// Parse a list of 1-based line numbers and ranges, like `3-5,9`.
+fn parse_line_ranges(
+    attribute: &str,
+    value: Option<&str>,
+) -> Result<Vec<RangeInclusive<usize>>, String> {
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
+
+    value
+        .split(',')
+        .map(|part| {
+            let part = part.trim();
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            match (start.parse::<usize>(), end.parse::<usize>()) {
+                (Ok(start), Ok(end)) if 0 < start && start <= end => {
+                    Ok(start..=end)
+                }
+                _ => Err(format!(
+                    "invalid line range '{part}' in `{attribute}`: expected line numbers like `3-5,9`"
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Whether mdBook treats `line` as a hidden line in a Rust code block: one
+/// starting with `#` which is not an attribute (`#[` or `#!`) or an escaped
+/// `##`.
+fn is_hidden_line(line: &str) -> bool {
+    match line.trim_start().strip_prefix('#') {
+        Some(rest) => !matches!(rest.chars().next(), Some('#' | '!' | '[')),
+        None => false,
+    }
+}
+
+/// The text mdBook shows for a Rust line: hidden lines lose their `# ` (or
+/// `#`) marker, and `##` is an escaped `#`.
+fn displayed_line(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let rest = line.trim_start();
+    match rest.strip_prefix('#') {
+        Some(escaped) if escaped.starts_with('#') => {
+            format!("{indent}{escaped}")
+        }
+        Some(hidden) if is_hidden_line(line) => {
+            let hidden = hidden.strip_prefix(' ').unwrap_or(hidden);
+            format!("{indent}{hidden}")
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// Render a code block as Markdown, with a fence longer than any run of
+/// backticks in the code.
+fn fenced_code_block(info: &str, code: &str) -> String {
+    let code = if code.ends_with('\n') {
+        code.to_string()
+    } else {
+        format!("{code}\n")
+    };
+
+    let longest_run = code
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+
+    format!("\n{fence}{info}\n{code}{fence}\n\n")
+}
+
+/// Render styled lines as an HTML code block, with each line wrapped in a
+/// `<span class="line">`.
+///
+/// This is the same markup mdBook itself produces for a code block, down to
+/// the `<span class="boring">` for hidden lines, which it would otherwise add
+/// itself: it only spots hidden lines at the start of a line, and every line
+/// here starts with a `<span>` instead. highlight.js keeps the spans when it
+/// highlights the code.
+fn lines_html(info: &str, lines: &[Line<'_>]) -> String {
+    // Like mdBook, the language gets a `language-` class, and each flag after
+    // it a class of its own, which its playground and highlight.js look for.
+    let mut words = info
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty());
+    let language = words.next();
+    let mut html = match language {
+        Some(language) => {
+            let classes: Vec<String> =
+                std::iter::once(format!("language-{language}"))
+                    .chain(words.map(String::from))
+                    .collect();
+            format!(
+                "\n<pre><code class=\"{}\">",
+                escape_html(&classes.join(" "))
+            )
+        }
+        None => String::from("\n<pre><code>"),
+    };
+
+    let is_rust = language == Some("rust");
+    for line in lines {
+        let text = if is_rust {
+            displayed_line(line.text)
+        } else {
+            line.text.to_string()
+        };
+        let text = escape_html(&text);
+
+        if line.hidden {
+            html.push_str(&format!("<span class=\"boring\">{text}\n</span>"));
+            continue;
+        }
+
+        let class = match line.style {
+            LineStyle::Normal => "line",
+            LineStyle::Highlight => "line highlighted",
+            LineStyle::Dim => "line dimmed",
+            LineStyle::Snip => "line snip",
+        };
+        let number = line
+            .number
+            .map(|number| format!(" data-line-number=\"{number}\""))
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<span class=\"{class}\"{number}>{text}</span>\n"
+        ));
+    }
+
+    html.push_str("</code></pre>\n\n");
+    html
+}
+
+/// Render styled lines as a Markdown code block, with a marker in the margin
+/// of each visible line when `markers` is set: `+` for highlighted lines, `.`
+/// for dimmed ones, and `~` for `--snip--` lines. Line numbers, if any, go
+/// after the marker.
+///
+/// Hidden lines are left exactly as they were, so that the
+/// `remove_hidden_lines` tool still recognizes them.
+fn lines_with_markers(info: &str, lines: &[Line<'_>], markers: bool) -> String {
+    let width = lines
+        .iter()
+        .filter_map(|line| line.number)
+        .max()
+        .map_or(0, |number| number.to_string().len());
+
+    let mut code = String::new();
+    for line in lines {
+        if line.hidden {
+            code.push_str(line.text);
+            code.push('\n');
+            continue;
+        }
+
+        if markers {
+            code.push(match line.style {
+                LineStyle::Normal => ' ',
+                LineStyle::Highlight => '+',
+                LineStyle::Dim => '.',
+                LineStyle::Snip => '~',
+            });
+            code.push(' ');
+        }
+        if let Some(number) = line.number {
+            code.push_str(&format!("{number:>width$} "));
+        }
+        code.push_str(line.text);
+        code.push('\n');
+    }
+
+    fenced_code_block(info, &code)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Quote a value for use in a Pandoc attribute list.
+fn quote_attribute(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+struct ListingBuilder {
+    id: Option<String>,
+    number: Option<String>,
+    caption: Option<String>,
+    file_name: Option<String>,
+    src: Option<String>,
+    anchor: Option<String>,
+    highlight: Option<String>,
+    dim: Option<String>,
+    line_numbers: Option<LineNumbers>,
+    kind: Kind,
+    output_for: Option<String>,
+    flags: Flags,
+}
+
+impl ListingBuilder {
+    fn new() -> ListingBuilder {
+        ListingBuilder {
+            id: None,
+            number: None,
+            caption: None,
+            file_name: None,
+            src: None,
+            anchor: None,
+            highlight: None,
+            dim: None,
+            line_numbers: None,
+            kind: Kind::Code,
+            output_for: None,
+            flags: Flags::default(),
+        }
+    }
+
+    fn from_attributes(attributes: Attributes) -> Result<Self, String> {
+        attributes
+            .into_iter()
+            .try_fold(ListingBuilder::new(), |builder, (key, maybe_value)| {
+                match (key.as_str(), maybe_value) {
+                    ("id", Some(value)) => Ok(builder.with_id(value)),
+                    ("id", None) => {
+                        Err(String::from("id attribute without value"))
+                    }
+                    ("number", Some(value)) => Ok(builder.with_number(value)),
+                    ("number", None) => {
+                        Err(String::from("number attribute without value"))
+                    }
+                    ("caption", Some(value)) => Ok(builder.with_caption(value)),
+                    ("caption", None) => {
+                        Err(String::from("caption attribute without value"))
+                    }
+                    ("file-name", Some(value)) => {
+                        Ok(builder.with_file_name(value))
+                    }
+                    ("file-name", None) => {
+                        Err(String::from("file-name attribute without value"))
+                    }
+                    ("src", Some(value)) => Ok(builder.with_src(value)),
+                    ("src", None) => {
+                        Err(String::from("src attribute without value"))
+                    }
+                    ("anchor", Some(value)) => Ok(builder.with_anchor(value)),
+                    ("anchor", None) => {
+                        Err(String::from("anchor attribute without value"))
+                    }
+                    ("highlight", Some(value)) => {
+                        Ok(builder.with_highlight(value))
+                    }
+                    ("highlight", None) => {
+                        Err(String::from("highlight attribute without value"))
+                    }
+                    ("dim", Some(value)) => Ok(builder.with_dim(value)),
+                    ("dim", None) => {
+                        Err(String::from("dim attribute without value"))
+                    }
+                    ("line-numbers", value) => {
+                        let line_numbers =
+                            LineNumbers::try_from(value.as_deref())?;
+                        Ok(builder.with_line_numbers(line_numbers))
+                    }
+                    ("kind", Some(value)) => {
+                        Ok(builder.with_kind(Kind::try_from(value.as_str())?))
+                    }
+                    ("kind", None) => {
+                        Err(String::from("kind attribute without value"))
+                    }
+                    ("for", Some(value)) => Ok(builder.with_output_for(value)),
+                    ("for", None) => {
+                        Err(String::from("for attribute without value"))
+                    }
+                    ("compiles", Some(value)) => Ok(builder
+                        .with_compiles(parse_flag("compiles", &value, "yes", "no")?)),
+                    ("compiles", None) => {
+                        Err(String::from("compiles attribute without value"))
+                    }
+                    ("panics", Some(value)) => Ok(builder
+                        .with_panics(parse_flag("panics", &value, "yes", "no")?)),
+                    ("panics", None) => {
+                        Err(String::from("panics attribute without value"))
+                    }
+                    ("playground", Some(value)) => Ok(builder.with_playground(
+                        parse_flag("playground", &value, "true", "false")?,
+                    )),
+                    ("playground", None) => {
+                        Err(String::from("playground attribute without value"))
+                    }
+
+                    (other, _) => Err(format!(
+                        "unknown attribute '{other}' on <Listing>: expected one of `id`, `number`, `caption`, `file-name`, `src`, `anchor`, `highlight`, `dim`, `line-numbers`, `kind`, `for`, `compiles`, `panics`, or `playground`"
+                    )),
+                }
+            })
+            .and_then(|builder| match (&builder.src, &builder.anchor) {
+                (None, Some(_)) => Err(String::from(
+                    "anchor attribute without a src attribute",
+                )),
+                _ => Ok(builder),
+            })
+            .and_then(|builder| {
+                parse_line_ranges("highlight", builder.highlight.as_deref())?;
+                parse_line_ranges("dim", builder.dim.as_deref())?;
+                Ok(builder)
+            })
+            .and_then(|builder| match (builder.kind, &builder.output_for) {
+                (Kind::Code, Some(_)) => Err(String::from(
+                    "for attribute on a listing which is not `kind=\"output\"`",
+                )),
+                _ => Ok(builder),
+            })
+            .and_then(|builder| {
+                match (builder.flags.compiles, builder.flags.panics) {
+                    (Some(false), Some(true)) => Err(String::from(
+                        "a listing with `compiles=\"no\"` cannot also have `panics=\"yes\"`",
+                    )),
+                    _ => Ok(builder),
+                }
+            })
+    }
+
+    fn with_id(mut self, value: String) -> Self {
+        self.id = Some(value);
+        self
+    }
+
+    fn with_number(mut self, value: String) -> Self {
+        self.number = Some(value);
+        self
+    }
+
+    fn with_caption(mut self, value: String) -> Self {
+        self.caption = Some(value);
+        self
+    }
+
+    fn with_file_name(mut self, value: String) -> Self {
+        self.file_name = Some(value);
+        self
+    }
+
+    fn with_src(mut self, value: String) -> Self {
+        self.src = Some(value);
+        self
+    }
+
+    fn with_anchor(mut self, value: String) -> Self {
+        self.anchor = Some(value);
+        self
+    }
+
+    fn with_highlight(mut self, value: String) -> Self {
+        self.highlight = Some(value);
+        self
+    }
+
+    fn with_dim(mut self, value: String) -> Self {
+        self.dim = Some(value);
+        self
+    }
+
+    fn with_line_numbers(mut self, value: LineNumbers) -> Self {
+        self.line_numbers = Some(value);
+        self
+    }
+
+    fn with_kind(mut self, value: Kind) -> Self {
+        self.kind = value;
+        self
+    }
+
+    fn with_output_for(mut self, value: String) -> Self {
+        self.output_for = Some(value);
+        self
+    }
+
+    fn with_compiles(mut self, value: bool) -> Self {
+        self.flags.compiles = Some(value);
+        self
+    }
+
+    fn with_panics(mut self, value: bool) -> Self {
+        self.flags.panics = Some(value);
+        self
+    }
+
+    fn with_playground(mut self, value: bool) -> Self {
+        self.flags.playground = Some(value);
+        self
+    }
+
+    fn build(self) -> Listing {
         Listing {
+            id: self.id,
             number: self.number.map(String::from),
-            caption,
+            caption: self.caption,
             file_name: self.file_name.map(String::from),
+            src: self.src,
+            anchor: self.anchor,
+            highlight: self.highlight,
+            dim: self.dim,
+            line_numbers: self.line_numbers,
+            kind: self.kind,
+            output_for: self.output_for,
+            output_of: None,
+            flags: self.flags,
         }
     }
 }
@@ -1,23 +1,23 @@
-use std::io;
+use std::{fs, path::PathBuf};
 
 use clap::{self, Parser, Subcommand};
-use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
+use similar::TextDiff;
+use trpl_preprocess::TrplPreprocessor;
 
-use mdbook_trpl_listing::TrplListing;
+use mdbook_trpl_listing::{
+    migrate::{migrate, Migration, Skipped},
+    TrplListing,
+};
 
 fn main() -> Result<(), String> {
     let cli = Cli::parse();
-    if let Some(Command::Supports { renderer }) = cli.command {
-        return if TrplListing.supports_renderer(&renderer) {
-            Ok(())
-        } else {
-            Err(format!("Renderer '{renderer}' is unsupported"))
-        };
+    match cli.command {
+        Some(Command::Supports { renderer }) => TrplListing.supports(&renderer),
+        Some(Command::Migrate { dry_run, paths }) => {
+            migrate_chapters(&paths, dry_run)
+        }
+        None => TrplListing.preprocess(),
     }
-
-    let (ctx, book) = CmdPreprocessor::parse_input(io::stdin()).map_err(|e| format!("{e}"))?;
-    let processed = TrplListing.run(&ctx, book).map_err(|e| format!("{e}"))?;
-    serde_json::to_writer(io::stdout(), &processed).map_err(|e| format!("{e}"))
 }
 
 /// A simple preprocessor for semantic markup for code listings in _The Rust
@@ -34,4 +34,46 @@ enum Command {
     ///
     /// All renderers are supported! This is the contract for mdBook.
     Supports { renderer: String },
+
+    /// Rewrite legacy `<span class="filename">` and `<span class="caption">`
+    /// listing markup in chapters to `<Listing>` elements, in place.
+    ///
+    /// Anything ambiguous is left alone and reported.
+    Migrate {
+        /// Print a diff of the changes instead of writing them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// The chapters to migrate.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+fn migrate_chapters(paths: &[PathBuf], dry_run: bool) -> Result<(), String> {
+    for path in paths {
+        let display = path.display();
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {display}: {e}"))?;
+
+        let Migration { output, skipped } = migrate(&src);
+        for Skipped { line, reason } in skipped {
+            eprintln!("{display}:{line}: skipped: {reason}");
+        }
+
+        if output == src {
+            continue;
+        }
+
+        if dry_run {
+            let diff = TextDiff::from_lines(&src, &output);
+            let name = display.to_string();
+            print!("{}", diff.unified_diff().header(&name, &name));
+        } else {
+            fs::write(path, output)
+                .map_err(|e| format!("Could not write {display}: {e}"))?;
+        }
+    }
+
+    Ok(())
 }
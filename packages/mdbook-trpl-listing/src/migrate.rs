@@ -0,0 +1,385 @@
+//! Convert the legacy listing markup to `<Listing>` elements.
+//!
+//! Before the `trpl-listing` preprocessor existed, listings were written like
+//! this:
+//!
+//! ````markdown
+//! <span class="filename">Filename: src/main.rs</span>
+//!
+//! ```rust
+//! fn main() {}
+//! ```
+//!
+//! <span class="caption">Listing 1-2: Some *text*, yeah?</span>
+//! ````
+//!
+//! [`migrate`] rewrites that to the equivalent:
+//!
+//! ````markdown
+//! <Listing number="1-2" file-name="src/main.rs" caption="Some *text*, yeah?">
+//!
+//! ```rust
+//! fn main() {}
+//! ```
+//!
+//! </Listing>
+//! ````
+//!
+//! Everything else in the chapter, including the code block itself, is left
+//! exactly as it was. Anything which does not clearly match that shape is left
+//! alone and reported instead, so that a person can sort it out.
+
+use pulldown_cmark::{CodeBlockKind, Event, Tag};
+use trpl_preprocess::rewrite::events;
+
+const FILENAME_START: &str = r#"<span class="filename">"#;
+const CAPTION_START: &str = r#"<span class="caption">"#;
+const SPAN_END: &str = "</span>";
+
+/// The result of migrating a single chapter.
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    /// The rewritten chapter source.
+    pub output: String,
+    /// Legacy markup which was left alone because it was ambiguous.
+    pub skipped: Vec<Skipped>,
+}
+
+/// A piece of legacy markup [`migrate`] refused to touch.
+#[derive(Debug, PartialEq)]
+pub struct Skipped {
+    /// The 1-based line in the original source.
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Rewrite the legacy listing markup in a chapter's source to `<Listing>`.
+pub fn migrate(src: &str) -> Migration {
+    let lines: Vec<&str> = src.split_inclusive('\n').collect();
+    let blocks = code_blocks(src);
+
+    let mut output = String::with_capacity(src.len());
+    let mut skipped = vec![];
+
+    // Lines with legacy spans which have already been dealt with.
+    let mut consumed = vec![false; lines.len()];
+    // Conversions, by the line they start on.
+    let mut conversions = Vec::new();
+
+    for (i, &(start, end)) in blocks.iter().enumerate() {
+        let mut filename = preceding_filename(&lines, start);
+        let mut caption = following_caption(&lines, end);
+
+        if filename.is_none() && caption.is_none() {
+            continue;
+        }
+
+        // A listing can only be wrapped around a code block at the start of a
+        // line, and when code blocks follow one another, which of them the
+        // filename or caption goes with is anybody's guess.
+        let indented = !lines[start].starts_with(['`', '~']);
+        let follows_block = i > 0 && is_adjacent(&lines, blocks[i - 1], start);
+        let followed_by_block = blocks
+            .get(i + 1)
+            .is_some_and(|&(next, _)| is_adjacent(&lines, (start, end), next));
+        let refuse_filename = if indented {
+            Some("filename span is followed by an indented code block")
+        } else if followed_by_block {
+            Some("filename span is followed by more than one code block")
+        } else {
+            None
+        };
+        let refuse_caption = if indented {
+            Some("listing caption span follows an indented code block")
+        } else if follows_block {
+            Some("listing caption span follows more than one code block")
+        } else {
+            None
+        };
+        if let (Some(Ok(found)), Some(reason)) = (&filename, refuse_filename) {
+            filename = Some(Err(Skipped {
+                line: found.line + 1,
+                reason: reason.to_string(),
+            }));
+        }
+        if let (Some(Ok(found)), Some(reason)) = (&caption, refuse_caption) {
+            caption = Some(Err(Skipped {
+                line: found.first_line + 1,
+                reason: reason.to_string(),
+            }));
+        }
+
+        // If either half is ambiguous, leave the whole listing alone, but only
+        // report the part which is actually a problem.
+        if matches!(filename, Some(Err(_))) || matches!(caption, Some(Err(_))) {
+            let lines = [
+                filename.map(|result| result.map(|f| f.line)),
+                caption.map(|result| result.map(|c| c.first_line)),
+            ];
+            for line in lines.into_iter().flatten() {
+                match line {
+                    Ok(line) => consumed[line] = true,
+                    Err(skip) => {
+                        consumed[skip.line - 1] = true;
+                        skipped.push(skip);
+                    }
+                }
+            }
+            continue;
+        }
+
+        let filename = filename.and_then(Result::ok);
+        let caption = caption.and_then(Result::ok);
+
+        let first = filename.as_ref().map_or(start, |f| f.line);
+        let last = caption.as_ref().map_or(end, |c| c.last_line);
+        for consumed in &mut consumed[first..=last] {
+            *consumed = true;
+        }
+
+        conversions.push((first, last, start, end, filename, caption));
+    }
+
+    // Anything which looks like legacy listing markup but was not part of a
+    // conversion is reported, rather than silently left behind.
+    let in_code_block = |line| {
+        blocks
+            .iter()
+            .any(|&(start, end)| start <= line && line <= end)
+    };
+    for (n, line) in lines.iter().enumerate() {
+        if consumed[n] || in_code_block(n) {
+            continue;
+        }
+
+        let line = line.trim_start();
+        if line.starts_with(FILENAME_START) {
+            skipped.push(Skipped {
+                line: n + 1,
+                reason: String::from(
+                    "filename span is not directly followed by a code block",
+                ),
+            });
+        } else if line.starts_with(CAPTION_START)
+            && caption_text_start(line).starts_with("Listing ")
+        {
+            skipped.push(Skipped {
+                line: n + 1,
+                reason: String::from(
+                    "listing caption span is not directly preceded by a code block",
+                ),
+            });
+        }
+    }
+
+    let mut n = 0;
+    let mut conversions = conversions.into_iter().peekable();
+    while n < lines.len() {
+        match conversions.next_if(|&(first, ..)| first == n) {
+            Some((_, last, start, end, filename, caption)) => {
+                output.push_str(&opening_tag(&filename, &caption));
+                output.push_str("\n\n");
+                for line in &lines[start..=end] {
+                    output.push_str(line);
+                }
+                if !lines[end].ends_with('\n') {
+                    output.push('\n');
+                }
+                output.push_str("\n</Listing>");
+                // Keep whatever line ending the last consumed line had.
+                if lines[last].ends_with('\n') {
+                    output.push('\n');
+                }
+                n = last + 1;
+            }
+            None => {
+                output.push_str(lines[n]);
+                n += 1;
+            }
+        }
+    }
+
+    skipped.sort_by_key(|skip| skip.line);
+    Migration { output, skipped }
+}
+
+struct Filename {
+    line: usize,
+    name: String,
+}
+
+struct Caption {
+    first_line: usize,
+    last_line: usize,
+    number: String,
+    text: String,
+}
+
+/// Find every fenced code block, as `(first line, last line)`, wherever it is
+/// and however it is fenced.
+fn code_blocks(src: &str) -> Vec<(usize, usize)> {
+    let line_of = |offset: usize| src[..offset].matches('\n').count();
+    events(src)
+        .into_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                let last = line_of(range.end.max(range.start + 1) - 1);
+                Some((line_of(range.start), last))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the code block starting at `start` comes right after `block`, with
+/// only blank lines in between.
+fn is_adjacent(lines: &[&str], block: (usize, usize), start: usize) -> bool {
+    let (_, end) = block;
+    lines[end + 1..start]
+        .iter()
+        .all(|line| line.trim().is_empty())
+}
+
+/// Look for a filename span before the code block starting at `start`, with
+/// only blank lines in between.
+fn preceding_filename(
+    lines: &[&str],
+    start: usize,
+) -> Option<Result<Filename, Skipped>> {
+    let n = (0..start).rev().find(|&n| !lines[n].trim().is_empty())?;
+
+    // A comment between the filename and the code block is probably there for
+    // a reason, and it is not obvious where it should end up.
+    if lines[n].trim_end().ends_with("-->") {
+        let filename = (0..n)
+            .rev()
+            .find(|&n| lines[n].trim_start().starts_with(FILENAME_START))
+            .filter(|&f| {
+                lines[f + 1..n]
+                    .iter()
+                    .all(|l| !l.contains("```") && !l.contains("~~~"))
+            })?;
+        return Some(Err(Skipped {
+            line: filename + 1,
+            reason: String::from(
+                "filename span is separated from its code block by a comment",
+            ),
+        }));
+    }
+
+    let line = lines[n].trim();
+    let inner = line.strip_prefix(FILENAME_START)?;
+
+    let skip = |reason: &str| {
+        Some(Err(Skipped {
+            line: n + 1,
+            reason: reason.to_string(),
+        }))
+    };
+
+    let Some(inner) = inner.strip_suffix(SPAN_END) else {
+        return skip("filename span does not end on the same line");
+    };
+    let Some(name) = inner.strip_prefix("Filename: ") else {
+        return skip("filename span does not start with `Filename: `");
+    };
+    if name.contains('"') {
+        return skip("filename contains a `\"`");
+    }
+
+    Some(Ok(Filename {
+        line: n,
+        name: name.to_string(),
+    }))
+}
+
+/// Look for a listing caption span after the code block ending at `end`, with
+/// only blank lines in between.
+fn following_caption(
+    lines: &[&str],
+    end: usize,
+) -> Option<Result<Caption, Skipped>> {
+    let first =
+        (end + 1..lines.len()).find(|&n| !lines[n].trim().is_empty())?;
+    if !lines[first].trim_start().starts_with(CAPTION_START) {
+        return None;
+    }
+
+    // Figures and tables use the same markup, but are not listings.
+    let start = caption_text_start(lines[first]);
+    if !start.starts_with("Listing ") {
+        return None;
+    }
+
+    let skip = |reason: &str| {
+        Some(Err(Skipped {
+            line: first + 1,
+            reason: reason.to_string(),
+        }))
+    };
+
+    // Captions sometimes take up multiple lines.
+    let Some(last) =
+        (first..lines.len()).find(|&n| lines[n].contains(SPAN_END))
+    else {
+        return skip("caption span is never closed");
+    };
+    if last > first
+        && lines[first + 1..=last].iter().any(|l| l.trim().is_empty())
+    {
+        return skip("caption span contains a blank line");
+    }
+
+    let closing = lines[last].trim_end();
+    if !closing.ends_with(SPAN_END) {
+        return skip("caption span has text after the closing `</span>`");
+    }
+
+    let mut text = std::iter::once(start)
+        .chain(lines[first + 1..=last].iter().copied())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.truncate(text.len() - SPAN_END.len());
+
+    let Some((label, caption)) = text.split_once(": ") else {
+        return skip("caption does not have a `Listing N-M: ` label");
+    };
+    let number = label.trim_start_matches("Listing ");
+    if crate::parse_number(number).is_err() {
+        return skip("caption does not have a valid listing number");
+    }
+    if caption.contains('"') {
+        return skip("caption contains a `\"`");
+    }
+
+    Some(Ok(Caption {
+        first_line: first,
+        last_line: last,
+        number: number.to_string(),
+        text: caption.trim().to_string(),
+    }))
+}
+
+/// The text of a caption span on its first line, after the opening tag.
+fn caption_text_start(line: &str) -> &str {
+    line.trim().strip_prefix(CAPTION_START).unwrap_or_default()
+}
+
+fn opening_tag(
+    filename: &Option<Filename>,
+    caption: &Option<Caption>,
+) -> String {
+    let mut tag = String::from("<Listing");
+    if let Some(caption) = caption {
+        tag.push_str(&format!(r#" number="{}""#, caption.number));
+    }
+    if let Some(filename) = filename {
+        tag.push_str(&format!(r#" file-name="{}""#, filename.name));
+    }
+    if let Some(caption) = caption {
+        tag.push_str(&format!(r#" caption="{}""#, caption.text));
+    }
+    tag.push('>');
+    tag
+}
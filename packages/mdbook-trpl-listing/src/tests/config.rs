@@ -6,6 +6,8 @@
 //! more complex in the future, it would be good to revisit and integrate
 //! the same kinds of tests as the unit tests above here.
 
+use mdbook::BookItem;
+
 use super::*;
 
 // TODO: what *should* the behavior here be? I *think* it should error,
@@ -190,6 +192,70 @@ fn specify_simple() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn specify_print() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": "print"
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "# Chapter 1\n\n<Listing number=\"1-1\" file-name=\"src/main.rs\" caption=\"Hello\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert_eq!(
+        chapter.content,
+        r#"# Chapter 1
+
+:::: {#listing-1-1 .listing number="1-1" file="src/main.rs"}
+
+```rust
+fn main() {}
+```
+
+::: caption
+Hello
+:::
+::::
+"#
+    );
+}
+
 #[test]
 fn specify_invalid() {
     let input_json = r##"[
@@ -240,3 +306,492 @@ fn specify_invalid() {
         "Bad config value '\"nonsense\"' for key 'output-mode'"
     );
 }
+
+#[test]
+fn specify_permalinks() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "permalinks": true
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "# Chapter 1\n\n<Listing number=\"1-1\" caption=\"Hello\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert_eq!(
+        chapter.content,
+        r##"# Chapter 1
+
+<figure class="listing" id="listing-1-1">
+
+```rust
+fn main() {}
+```
+
+<figcaption><a href="#listing-1-1">Listing 1-1</a>: Hello</figcaption>
+</figure>
+"##
+    );
+}
+
+#[test]
+fn specify_invalid_permalinks() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "permalinks": "yes"
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "# Chapter 1\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let result = TrplListing.run(&ctx, book);
+    assert!(result.is_err());
+    let err = result.unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        "Bad config value '\"yes\"' for key 'permalinks'"
+    );
+}
+
+#[test]
+fn list_of_listings_placeholder() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "list-of-listings": "listings.md"
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "# Chapter 1\n\n<Listing number=\"1-1\" caption=\"Hello\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        },
+                        {
+                            "Chapter": {
+                                "name": "All the Listings",
+                                "content": "",
+                                "number": null,
+                                "sub_items": [],
+                                "path": "listings.md",
+                                "source_path": "listings.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    assert_eq!(book.sections.len(), 2);
+    let BookItem::Chapter(chapter) = &book.sections[1] else {
+        panic!("expected a chapter");
+    };
+    assert_eq!(
+        chapter.content,
+        "# All the Listings
+
+| Listing | Caption | File name | Chapter |
+| ------- | ------- | --------- | ------- |
+| [Listing 1-1](chapter_1.md#listing-1-1) | Hello |  | [Chapter 1](chapter_1.md) |
+"
+    );
+}
+
+#[test]
+fn list_of_listings_added() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": "simple",
+                                "list-of-listings": "listings.md"
+                            }
+                        }
+                    },
+                    "renderer": "markdown",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "# Chapter 1\n\n<Listing number=\"1-1\" caption=\"Hello\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    assert_eq!(book.sections.len(), 2);
+    let BookItem::Chapter(chapter) = &book.sections[1] else {
+        panic!("expected a chapter");
+    };
+    assert_eq!(chapter.path, Some(PathBuf::from("listings.md")));
+    assert_eq!(
+        chapter.content,
+        "# List of Listings
+
+| Listing | Caption | File name | Chapter |
+| ------- | ------- | --------- | ------- |
+| [Listing 1-1](chapter_1.md#listing-1-1) | Hello |  | [Chapter 1](chapter_1.md) |
+"
+    );
+}
+
+#[test]
+fn string_mode_applies_to_every_renderer() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": "simple"
+                            }
+                        }
+                    },
+                    "renderer": "markdown",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "<Listing number=\"1-1\" file-name=\"src/main.rs\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert!(chapter.content.starts_with("Filename: src/main.rs\n"));
+}
+
+#[test]
+fn per_renderer_mode_for_html() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": { "html": "default", "markdown": "simple" }
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "<Listing number=\"1-1\" file-name=\"src/main.rs\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert!(chapter.content.starts_with(r#"<figure class="listing""#));
+}
+
+#[test]
+fn per_renderer_mode_for_markdown() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": { "html": "default", "markdown": "simple" }
+                            }
+                        }
+                    },
+                    "renderer": "markdown",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "<Listing number=\"1-1\" file-name=\"src/main.rs\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert!(chapter.content.starts_with("Filename: src/main.rs\n"));
+}
+
+#[test]
+fn per_renderer_mode_for_other_renderer() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": { "markdown": "simple" }
+                            }
+                        }
+                    },
+                    "renderer": "test",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "<Listing number=\"1-1\" file-name=\"src/main.rs\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let book = TrplListing.run(&ctx, book).unwrap();
+    let BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert!(chapter.content.starts_with(r#"<figure class="listing""#));
+}
+
+#[test]
+fn per_renderer_mode_invalid() {
+    let input_json = r##"[
+                {
+                    "root": "/path/to/book",
+                    "config": {
+                        "book": {
+                            "authors": ["AUTHOR"],
+                            "language": "en",
+                            "multilingual": false,
+                            "src": "src",
+                            "title": "TITLE"
+                        },
+                        "preprocessor": {
+                            "trpl-listing": {
+                                "output-mode": { "html": "default", "markdown": "nonsense" }
+                            }
+                        }
+                    },
+                    "renderer": "html",
+                    "mdbook_version": "0.4.21"
+                },
+                {
+                    "sections": [
+                        {
+                            "Chapter": {
+                                "name": "Chapter 1",
+                                "content": "<Listing number=\"1-1\" file-name=\"src/main.rs\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n",
+                                "number": [1],
+                                "sub_items": [],
+                                "path": "chapter_1.md",
+                                "source_path": "chapter_1.md",
+                                "parent_names": []
+                            }
+                        }
+                    ],
+                    "__non_exhaustive": null
+                }
+            ]"##;
+    let input_json = input_json.as_bytes();
+    let (ctx, book) =
+        mdbook::preprocess::CmdPreprocessor::parse_input(input_json).unwrap();
+    let result = TrplListing.run(&ctx, book);
+    assert!(result.is_err());
+    let err = result.unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        "Bad config value '\"nonsense\"' for key 'output-mode.markdown'"
+    );
+}
@@ -0,0 +1,224 @@
+//! Check that errors point at the right place in the chapter source.
+
+use std::path::Path;
+
+use super::rewrite_listing;
+use crate::{ChapterInfo, Config, ListingIndex};
+
+fn chapter() -> ChapterInfo<'static> {
+    ChapterInfo {
+        source_path: Some(Path::new("ch01.md")),
+        number: Some(1),
+        ..ChapterInfo::default()
+    }
+}
+
+#[test]
+fn renders_like_rustc() {
+    let errors = rewrite_listing(
+        r#"Some text.
+
+  <Listing number="1-1" filename="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    let diagnostic = &errors[0];
+    assert_eq!(
+        diagnostic.source_path.as_deref(),
+        Some(Path::new("ch01.md"))
+    );
+    assert_eq!(diagnostic.offset, 14);
+    assert_eq!((diagnostic.line, diagnostic.column), (3, 3));
+    assert_eq!(
+        diagnostic.render(),
+        r#"error: unknown attribute 'filename' on <Listing>: expected one of `id`, `number`, `caption`, `file-name`, `src`, `anchor`, `highlight`, `dim`, `line-numbers`, `kind`, `for`, `compiles`, `panics`, or `playground`
+ --> ch01.md:3:3
+  |
+3 |   <Listing number="1-1" filename="src/main.rs">
+  |   ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+"#
+    );
+}
+
+#[test]
+fn unclosed_listing_points_at_opening_tag() {
+    let errors = rewrite_listing(
+        r#"# Chapter 1
+
+<Listing number="1-1">
+
+```rust
+fn main() {}
+```
+"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let locations: Vec<_> = errors
+        .iter()
+        .map(|diagnostic| (diagnostic.to_string(), diagnostic.column))
+        .collect();
+    assert_eq!(
+        locations,
+        vec![(String::from("ch01.md:3: Unclosed listing"), 1)]
+    );
+}
+
+#[test]
+fn every_stray_closing_tag_is_reported() {
+    let errors = rewrite_listing(
+        "Some text.\n\n</Listing>\n\nMore text.\n\n</Listing>\n",
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let lines: Vec<_> =
+        errors.iter().map(|diagnostic| diagnostic.line).collect();
+    assert_eq!(lines, vec![3, 7]);
+    assert_eq!(
+        errors[0].message,
+        "Closing `</Listing>` without opening tag."
+    );
+}
+
+#[test]
+fn nested_listing() {
+    let errors = rewrite_listing(
+        r#"<Listing number="1-1">
+
+<Listing number="1-2">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+</Listing>
+"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let messages: Vec<_> = errors
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![String::from(
+            "ch01.md:3: `<Listing>` inside another listing; close that one with `</Listing>` first"
+        )]
+    );
+}
+
+#[test]
+fn text_after_closing_tag() {
+    let errors = rewrite_listing(
+        r#"<Listing number="1-1">
+
+```rust
+fn main() {}
+```
+
+</Listing> trailing text
+"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let locations: Vec<_> = errors
+        .iter()
+        .map(|diagnostic| (diagnostic.to_string(), diagnostic.column))
+        .collect();
+    assert_eq!(
+        locations,
+        vec![(
+            String::from(
+                "ch01.md:7: `</Listing>` must be on a line of its own"
+            ),
+            1
+        )]
+    );
+}
+
+#[test]
+fn text_after_opening_tag() {
+    let errors = rewrite_listing(
+        r#"Some text. <Listing number="1-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let locations: Vec<_> = errors
+        .iter()
+        .map(|diagnostic| (diagnostic.to_string(), diagnostic.column))
+        .collect();
+    assert_eq!(
+        locations,
+        vec![(
+            String::from(
+                "ch01.md:1: `<Listing number=\"1-1\">` must be on a line of its own"
+            ),
+            12
+        )]
+    );
+}
+
+#[test]
+fn every_bad_opening_tag_is_reported() {
+    let errors = rewrite_listing(
+        r#"<Listing number="1-1" filename="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number="one">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+"#,
+        Config::default(),
+        &ListingIndex::default(),
+        chapter(),
+    )
+    .unwrap_err();
+
+    let lines: Vec<_> =
+        errors.iter().map(|diagnostic| diagnostic.line).collect();
+    assert_eq!(lines, vec![1, 9]);
+}
@@ -0,0 +1,171 @@
+//! Check listings which show several files.
+
+use super::{messages, rewrite_listing};
+use crate::{ChapterInfo, Config, ListingIndex, Mode};
+
+const LISTING: &str = r#"<Listing number="14-7" caption="A workspace with two crates">
+
+<File name="adder/src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</File>
+
+<File name="add_one/src/lib.rs">
+
+```rust
+pub fn add_one(x: i32) -> i32 {
+    x + 1
+}
+```
+
+</File>
+
+</Listing>"#;
+
+fn rewrite(src: &str, mode: Mode) -> Result<String, Vec<String>> {
+    rewrite_listing(
+        src,
+        Config {
+            mode,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    )
+    .map_err(messages)
+}
+
+#[test]
+fn default_mode() {
+    assert_eq!(
+        rewrite(LISTING, Mode::Default).unwrap(),
+        r#"<figure class="listing" id="listing-14-7">
+
+<div class="listing-file">
+<span class="file-name">Filename: adder/src/main.rs</span>
+
+```rust
+fn main() {}
+```
+
+</div>
+
+<div class="listing-file">
+<span class="file-name">Filename: add_one/src/lib.rs</span>
+
+```rust
+pub fn add_one(x: i32) -> i32 {
+    x + 1
+}
+```
+
+</div>
+
+<figcaption>Listing 14-7: A workspace with two crates</figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn simple_mode() {
+    let result = rewrite(LISTING, Mode::Simple).unwrap();
+
+    // Blank lines are not significant here, only the order of things.
+    let lines: Vec<_> = result.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Filename: adder/src/main.rs",
+            "```rust",
+            "fn main() {}",
+            "```",
+            "Filename: add_one/src/lib.rs",
+            "```rust",
+            "pub fn add_one(x: i32) -> i32 {",
+            "    x + 1",
+            "}",
+            "```",
+            "Listing 14-7: A workspace with two crates",
+        ]
+    );
+}
+
+#[test]
+fn print_mode() {
+    assert_eq!(
+        rewrite(LISTING, Mode::Print).unwrap(),
+        r#":::: {#listing-14-7 .listing number="14-7"}
+
+::: {.file name="adder/src/main.rs"}
+
+```rust
+fn main() {}
+```
+
+:::
+
+::: {.file name="add_one/src/lib.rs"}
+
+```rust
+pub fn add_one(x: i32) -> i32 {
+    x + 1
+}
+```
+
+:::
+
+::: caption
+A workspace with two crates
+:::
+::::"#
+    );
+}
+
+#[test]
+fn invalid_files() {
+    let cases = [
+        (
+            "<File name=\"src/main.rs\">\n\n```rust\n```\n\n</File>",
+            vec![
+                "line 1: `<File>` outside of a `<Listing>`",
+                "line 6: Closing `</File>` without opening tag.",
+            ],
+        ),
+        (
+            "<Listing file-name=\"src/main.rs\">\n\n<File name=\"src/lib.rs\">\n\n</File>\n\n</Listing>",
+            vec![
+                "line 3: a listing with a `file-name` or `src` attribute cannot also contain `<File>` elements",
+                "line 5: Closing `</File>` without opening tag.",
+            ],
+        ),
+        (
+            "<Listing>\n\n<File>\n\n</File>\n\n</Listing>",
+            vec![
+                "line 3: <File> without a name attribute",
+                "line 5: Closing `</File>` without opening tag.",
+            ],
+        ),
+        (
+            "<Listing>\n\n<File path=\"src/lib.rs\">\n\n</File>\n\n</Listing>",
+            vec![
+                "line 3: unknown attribute 'path' on <File>: expected `name`",
+                "line 5: Closing `</File>` without opening tag.",
+            ],
+        ),
+        (
+            "<Listing>\n\n<File name=\"a.rs\">\n\n<File name=\"b.rs\">\n\n</File>\n\n</Listing>",
+            vec!["line 5: `<File>` inside another `<File>`"],
+        ),
+        (
+            "<Listing>\n\n<File name=\"a.rs\">\n\n```rust\n```\n\n</Listing>",
+            vec!["line 8: Unclosed `<File>` in listing"],
+        ),
+    ];
+
+    for (src, expected) in cases {
+        assert_eq!(rewrite(src, Mode::Default).unwrap_err(), expected, "{src}");
+    }
+}
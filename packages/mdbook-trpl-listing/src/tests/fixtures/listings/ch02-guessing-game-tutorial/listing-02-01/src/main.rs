@@ -0,0 +1,7 @@
+use std::io;
+
+fn main() {
+    // ANCHOR: here
+    println!("Guess the number!");
+    // ANCHOR_END: here
+}
@@ -0,0 +1,204 @@
+//! Check turning `compiles`, `panics`, and `playground` into info strings.
+
+use std::path::Path;
+
+use trpl_preprocess::stage;
+
+use super::{book_with, messages, rewrite_listing, FIXTURES};
+use crate::{ChapterInfo, Config, ListingIndex, Mode};
+
+fn rewrite(src: &str) -> Result<String, Vec<String>> {
+    rewrite_listing(
+        src,
+        Config {
+            mode: Mode::Print,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    )
+    .map_err(messages)
+}
+
+#[test]
+fn adds_flags_to_info_string() {
+    let cases = [
+        (
+            r#"compiles="no""#,
+            "```rust",
+            "```rust,ignore,does_not_compile",
+        ),
+        (r#"panics="yes""#, "```rust", "```rust,should_panic,panics"),
+        (r#"playground="false""#, "```rust", "```rust,noplayground"),
+        (
+            r#"compiles="no" playground="false""#,
+            "```rust,ignore",
+            "```rust,ignore,does_not_compile,noplayground",
+        ),
+        // Flags which are already there are left as they are, and new ones
+        // are separated the same way.
+        (
+            r#"panics="yes""#,
+            "~~~ rust should_panic ",
+            "~~~ rust should_panic panics ",
+        ),
+        (
+            r#"compiles="no""#,
+            "```rust,ignore",
+            "```rust,ignore,does_not_compile",
+        ),
+        (r#"compiles="yes" panics="no""#, "```rust", "```rust"),
+        // A code block without a language is Rust, as it is to rustdoc, so
+        // the flags do not end up as its language.
+        (r#"playground="false""#, "```", "```rust,noplayground"),
+        (r#"compiles="no""#, "```", "```rust,ignore,does_not_compile"),
+    ];
+
+    for (attributes, fence, expected) in cases {
+        let close = &fence[..3];
+        let src = format!(
+            "<Listing {attributes}>\n\n{fence}\nfn main() {{}}\n{close}\n\n</Listing>"
+        );
+        assert_eq!(
+            rewrite(&src).unwrap(),
+            format!(
+                ":::: {{.listing}}\n\n{expected}\nfn main() {{}}\n{close}\n\n::::"
+            ),
+            "{attributes} {fence}"
+        );
+    }
+}
+
+#[test]
+fn flags_every_file() {
+    let src = r#"<Listing compiles="no">
+
+<File name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</File>
+
+<File name="src/lib.rs">
+
+```rust,ignore
+pub fn f() {}
+```
+
+</File>
+
+</Listing>"#;
+
+    let result = rewrite(src).unwrap();
+    assert!(result.contains("```rust,ignore,does_not_compile\nfn main"));
+    assert!(result.contains("```rust,ignore,does_not_compile\npub fn f"));
+}
+
+#[test]
+fn flags_rendered_lines() {
+    let src = r#"<Listing compiles="no" highlight="1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#;
+
+    assert_eq!(
+        rewrite_listing(
+            src,
+            Config::default(),
+            &ListingIndex::default(),
+            ChapterInfo::default(),
+        )
+        .unwrap(),
+        r#"<figure class="listing">
+
+<pre><code class="language-rust ignore does_not_compile"><span class="line highlighted">fn main() {}</span>
+</code></pre>
+
+</figure>"#
+    );
+}
+
+#[test]
+fn flags_listing_from_src() {
+    let src = r#"<Listing src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs" playground="false">
+</Listing>"#;
+
+    assert!(rewrite(src).unwrap().contains("```rust,noplayground\n"));
+}
+
+#[test]
+fn marks_listing_as_not_compiling() {
+    let book = book_with(vec![(
+        2,
+        "ch02.md",
+        "<Listing number=\"2-1\" compiles=\"no\">\n\n```rust\n```\n\n</Listing>",
+    )]);
+    let index = ListingIndex::build(&stage::parse(&book)).unwrap();
+    assert!(index.get_for("2-1").unwrap().does_not_compile);
+}
+
+#[test]
+fn conflicts() {
+    let cases = [
+        (
+            r#"compiles="yes""#,
+            "```rust,ignore,does_not_compile",
+            "line 3: `compiles=\"yes\"` conflicts with `does_not_compile` in the code block's info string",
+        ),
+        (
+            r#"panics="no""#,
+            "```rust should_panic",
+            "line 3: `panics=\"no\"` conflicts with `should_panic` in the code block's info string",
+        ),
+        (
+            r#"playground="true""#,
+            "```rust,noplayground",
+            "line 3: `playground=\"true\"` conflicts with `noplayground` in the code block's info string",
+        ),
+    ];
+
+    for (attributes, fence, expected) in cases {
+        let src = format!(
+            "<Listing {attributes}>\n\n{fence}\nfn main() {{}}\n```\n\n</Listing>"
+        );
+        assert_eq!(rewrite(&src).unwrap_err(), vec![expected], "{src}");
+    }
+}
+
+#[test]
+fn invalid_flags() {
+    let cases = [
+        (
+            r#"compiles="maybe""#,
+            "line 1: invalid `compiles` 'maybe': expected `yes` or `no`",
+        ),
+        (
+            r#"playground="no""#,
+            "line 1: invalid `playground` 'no': expected `true` or `false`",
+        ),
+        ("panics", "line 1: panics attribute without value"),
+        (
+            r#"compiles="no" panics="yes""#,
+            "line 1: a listing with `compiles=\"no\"` cannot also have `panics=\"yes\"`",
+        ),
+    ];
+
+    for (attributes, expected) in cases {
+        let src = format!("<Listing {attributes}>\n</Listing>");
+        assert_eq!(rewrite(&src).unwrap_err(), vec![expected], "{src}");
+    }
+
+    let src = "<Listing compiles=\"no\">\n\n    fn main() {}\n\n</Listing>";
+    assert_eq!(
+        rewrite(src).unwrap_err(),
+        vec!["line 3: `compiles`, `panics`, and `playground` need a fenced code block to add flags to"]
+    );
+}
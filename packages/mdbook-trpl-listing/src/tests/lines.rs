@@ -0,0 +1,311 @@
+//! Check highlighting and dimming lines of code in listings.
+
+use std::path::Path;
+
+use super::{messages, rewrite_listing, FIXTURES};
+use crate::{ChapterInfo, Config, ListingIndex, Mode};
+
+/// A listing the way it looks once mdBook's `links` preprocessor has expanded
+/// a `{{#rustdoc_include}}`, with hidden lines around the visible ones.
+const LISTING: &str = r#"<Listing number="1-1" highlight="3-4" dim="1">
+
+```rust
+# #![allow(unused)]
+use std::io;
+# fn main() {
+fn read() -> String {
+    let mut s = String::new();
+    // --snip--
+    s
+}
+# }
+```
+
+</Listing>"#;
+
+fn rewrite(src: &str, mode: Mode) -> String {
+    rewrite_listing(
+        src,
+        Config {
+            mode,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn default_mode() {
+    assert_eq!(
+        rewrite(LISTING, Mode::Default),
+        r#"<figure class="listing" id="listing-1-1">
+
+<pre><code class="language-rust"><span class="boring">#![allow(unused)]
+</span><span class="line dimmed">use std::io;</span>
+<span class="boring">fn main() {
+</span><span class="line">fn read() -&gt; String {</span>
+<span class="line highlighted">    let mut s = String::new();</span>
+<span class="line snip">    // --snip--</span>
+<span class="line">    s</span>
+<span class="line">}</span>
+<span class="boring">}
+</span></code></pre>
+
+<figcaption>Listing 1-1</figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn simple_mode() {
+    assert_eq!(
+        rewrite(LISTING, Mode::Simple),
+        r#"
+
+```rust
+# #![allow(unused)]
+. use std::io;
+# fn main() {
+  fn read() -> String {
++     let mut s = String::new();
+~     // --snip--
+      s
+  }
+# }
+```
+
+Listing 1-1"#
+    );
+}
+
+#[test]
+fn print_mode() {
+    assert_eq!(
+        rewrite(LISTING, Mode::Print),
+        r#":::: {#listing-1-1 .listing number="1-1" highlight="3-4" dim="1"}
+
+```rust
+# #![allow(unused)]
+use std::io;
+# fn main() {
+fn read() -> String {
+    let mut s = String::new();
+    // --snip--
+    s
+}
+# }
+```
+
+::::"#
+    );
+}
+
+#[test]
+fn from_src_with_anchor() {
+    let result = rewrite_listing(
+        r#"<Listing number="2-1" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs" anchor="here" highlight="1">
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        result.unwrap(),
+        r#"<figure class="listing" id="listing-2-1">
+
+<pre><code class="language-rust"><span class="boring">use std::io;
+</span><span class="boring">
+</span><span class="boring">fn main() {
+</span><span class="line highlighted">    println!(&quot;Guess the number!&quot;);</span>
+<span class="boring">}
+</span></code></pre>
+
+<figcaption>Listing 2-1</figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        (
+            "<Listing highlight=\"2-3\">\n\n```text\na\nb\n```\n\n</Listing>",
+            "line 3: `highlight` refers to line 3, but the code block only has 2 lines",
+        ),
+        (
+            "<Listing highlight=\"1\" dim=\"1\">\n\n```text\na\n```\n\n</Listing>",
+            "line 3: line 1 is both highlighted and dimmed",
+        ),
+        (
+            "<Listing dim=\"3,x\">\n</Listing>",
+            "line 1: invalid line range 'x' in `dim`: expected line numbers like `3-5,9`",
+        ),
+        (
+            "<Listing highlight=\"5-2\">\n</Listing>",
+            "line 1: invalid line range '5-2' in `highlight`: expected line numbers like `3-5,9`",
+        ),
+    ];
+
+    for (src, expected) in cases {
+        let result = rewrite_listing(
+            src,
+            Config::default(),
+            &ListingIndex::default(),
+            ChapterInfo::default(),
+        );
+        assert_eq!(messages(result.unwrap_err()), vec![expected], "{src}");
+    }
+}
+
+#[test]
+fn line_numbers() {
+    let src = r#"<Listing line-numbers>
+
+```text
+one
+two
+```
+
+</Listing>"#;
+
+    assert_eq!(
+        rewrite(src, Mode::Default),
+        r#"<figure class="listing">
+
+<pre><code class="language-text"><span class="line" data-line-number="1">one</span>
+<span class="line" data-line-number="2">two</span>
+</code></pre>
+
+</figure>"#
+    );
+
+    let src = src.replace("line-numbers", r#"line-numbers="9" highlight="2""#);
+    assert_eq!(
+        rewrite(&src, Mode::Simple),
+        r#"
+
+```text
+   9 one
++ 10 two
+```
+
+"#
+    );
+    assert_eq!(
+        rewrite(&src, Mode::Print),
+        r#":::: {.listing highlight="2"}
+
+```{.text .numberLines startFrom="9"}
+one
+two
+```
+
+::::"#
+    );
+}
+
+#[test]
+fn source_line_numbers_from_src() {
+    let src = r#"<Listing number="2-1" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs" anchor="here" line-numbers="source">
+</Listing>"#;
+    let rewrite = |mode| {
+        rewrite_listing(
+            src,
+            Config {
+                mode,
+                ..Config::default()
+            },
+            &ListingIndex::default(),
+            ChapterInfo {
+                root: Some(Path::new(FIXTURES)),
+                ..ChapterInfo::default()
+            },
+        )
+        .unwrap()
+    };
+
+    // The anchor comment is line 4 of the file, so the snippet starts at 5.
+    assert!(rewrite(Mode::Default).contains(
+        r#"<span class="line" data-line-number="5">    println!(&quot;Guess the number!&quot;);</span>"#
+    ));
+    assert_eq!(
+        rewrite(Mode::Simple),
+        r#"```rust
+# use std::io;
+# 
+# fn main() {
+5     println!("Guess the number!");
+# }
+```
+
+Listing 2-1"#
+    );
+}
+
+#[test]
+fn source_line_numbers_from_include() {
+    let result = rewrite_listing(
+        r#"<Listing line-numbers="source">
+
+```rust
+{{#rustdoc_include listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs:here}}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            src_dir: Some(Path::new(FIXTURES)),
+            source_path: Some(Path::new("ch02.md")),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        result.unwrap(),
+        r#"<figure class="listing">
+
+<pre><code class="language-rust"><span class="boring">use std::io;
+</span><span class="boring">
+</span><span class="boring">fn main() {
+</span><span class="line" data-line-number="5">    println!(&quot;Guess the number!&quot;);</span>
+<span class="boring">}
+</span></code></pre>
+
+</figure>"#
+    );
+}
+
+#[test]
+fn line_number_errors() {
+    let cases = [
+        (
+            "<Listing line-numbers=\"source\">\n\n```rust\nfn main() {}\n```\n\n</Listing>",
+            "line 3: `line-numbers=\"source\"` needs code from a file, through `src` or `{{#rustdoc_include}}`",
+        ),
+        (
+            "<Listing line-numbers=\"source\">\n\n```rust\n{{#rustdoc_include main.rs:3:5}}\n```\n\n</Listing>",
+            "line 3: line ranges like 'main.rs:3:5' are not supported in listings with `highlight`, `dim`, or `line-numbers`; use an anchor instead",
+        ),
+        (
+            "<Listing line-numbers=\"0\">\n</Listing>",
+            "line 1: invalid `line-numbers` value '0': expected a line number or `source`",
+        ),
+    ];
+
+    for (src, expected) in cases {
+        let result = rewrite_listing(
+            src,
+            Config::default(),
+            &ListingIndex::default(),
+            ChapterInfo::default(),
+        );
+        assert_eq!(messages(result.unwrap_err()), vec![expected], "{src}");
+    }
+}
@@ -0,0 +1,274 @@
+//! Check the conversion of legacy listing markup to `<Listing>`.
+
+use crate::migrate::{migrate, Migration, Skipped};
+
+#[test]
+fn full_listing() {
+    let src = r#"Some text.
+
+<span class="filename">Filename: src/main.rs</span>
+
+```rust
+{{#rustdoc_include ../listings/ch15-smart-pointers/listing-15-17/src/main.rs}}
+```
+
+<span class="caption">Listing 15-17: Demonstrating we’re not allowed to have
+two lists using `Box<T>` that try to share ownership of a third list</span>
+
+More text.
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(
+                r#"Some text.
+
+<Listing number="15-17" file-name="src/main.rs" caption="Demonstrating we’re not allowed to have two lists using `Box<T>` that try to share ownership of a third list">
+
+```rust
+{{#rustdoc_include ../listings/ch15-smart-pointers/listing-15-17/src/main.rs}}
+```
+
+</Listing>
+
+More text.
+"#
+            ),
+            skipped: vec![],
+        }
+    );
+}
+
+#[test]
+fn caption_only() {
+    let src = r#"```rust
+fn main() {}
+```
+
+<span class="caption">Listing 4-1: A variable</span>"#;
+
+    assert_eq!(
+        migrate(src).output,
+        r#"<Listing number="4-1" caption="A variable">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#
+    );
+}
+
+#[test]
+fn filename_only() {
+    let src = r#"<span class="filename">Filename: src/lib.rs</span>
+
+```rust
+fn main() {}
+```
+
+Some text.
+"#;
+
+    assert_eq!(
+        migrate(src).output,
+        r#"<Listing file-name="src/lib.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+Some text.
+"#
+    );
+}
+
+#[test]
+fn leaves_everything_else_alone() {
+    let src = r#"# A chapter
+
+````markdown
+<span class="filename">Filename: src/main.rs</span>
+
+```rust
+fn main() {}
+```
+````
+
+<img alt="A picture" src="img/trpl15-03.svg" class="center" />
+
+<span class="caption">Figure 15-3: A picture</span>
+
+| A | Table |
+|---|-------|
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(src),
+            skipped: vec![],
+        }
+    );
+}
+
+#[test]
+fn refuses_ambiguous_listings() {
+    let src = r#"<span class="filename">Filename: src/main.rs</span>
+
+<!-- a comment -->
+
+```rust
+fn main() {}
+```
+
+<span class="caption">Listing 9-5: Handling errors</span>
+
+<span class="filename">Filename: src/lib.rs</span>
+
+Some text which is not a code block.
+
+```rust
+fn main() {}
+```
+
+<span class="caption">Listing 9-6: A "quoted" caption</span>
+
+<span class="caption">Listing 9-7: Nothing to caption</span>
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(src),
+            skipped: vec![
+                Skipped {
+                    line: 1,
+                    reason: String::from(
+                        "filename span is separated from its code block by a comment"
+                    ),
+                },
+                Skipped {
+                    line: 11,
+                    reason: String::from(
+                        "filename span is not directly followed by a code block"
+                    ),
+                },
+                Skipped {
+                    line: 19,
+                    reason: String::from("caption contains a `\"`"),
+                },
+                Skipped {
+                    line: 21,
+                    reason: String::from(
+                        "listing caption span is not directly preceded by a code block"
+                    ),
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn tilde_fences() {
+    let src = r#"<span class="filename">Filename: src/main.rs</span>
+
+~~~rust
+fn main() {}
+~~~
+
+<span class="caption">Listing 3-1: With tildes</span>
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(
+                r#"<Listing number="3-1" file-name="src/main.rs" caption="With tildes">
+
+~~~rust
+fn main() {}
+~~~
+
+</Listing>
+"#
+            ),
+            skipped: vec![],
+        }
+    );
+}
+
+#[test]
+fn refuses_indented_code_blocks() {
+    let src = r#"1. A step.
+
+   <span class="filename">Filename: src/main.rs</span>
+
+   ```rust
+   fn main() {}
+   ```
+
+   <span class="caption">Listing 3-2: In a list</span>
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(src),
+            skipped: vec![
+                Skipped {
+                    line: 3,
+                    reason: String::from(
+                        "filename span is followed by an indented code block"
+                    ),
+                },
+                Skipped {
+                    line: 9,
+                    reason: String::from(
+                        "listing caption span follows an indented code block"
+                    ),
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn refuses_several_code_blocks() {
+    let src = r#"<span class="filename">Filename: src/main.rs</span>
+
+```rust
+fn main() {}
+```
+
+```console
+$ cargo run
+```
+
+<span class="caption">Listing 3-3: Which one?</span>
+"#;
+
+    assert_eq!(
+        migrate(src),
+        Migration {
+            output: String::from(src),
+            skipped: vec![
+                Skipped {
+                    line: 1,
+                    reason: String::from(
+                        "filename span is followed by more than one code block"
+                    ),
+                },
+                Skipped {
+                    line: 11,
+                    reason: String::from(
+                        "listing caption span follows more than one code block"
+                    ),
+                },
+            ],
+        }
+    );
+}
@@ -1,8 +1,8 @@
+use mdbook::book::{Chapter, SectionNumber};
+use trpl_preprocess::rewrite::{events, splice};
+
 use super::*;
 
-/// Note: This inserts an additional backtick around the re-emitted code.
-/// It is not clear *why*, but that seems to be an artifact of the rendering
-/// done by the `pulldown_cmark_to_cmark` crate.
 #[test]
 fn default_mode_works() {
     let result = rewrite_listing(
@@ -13,17 +13,19 @@ fn main() {}
 ```
 
 </Listing>"#,
-        Mode::Default,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert_eq!(
         &result.unwrap(),
-        r#"<figure class="listing">
+        r#"<figure class="listing" id="listing-1-2">
 <span class="file-name">Filename: src/main.rs</span>
 
-````rust
+```rust
 fn main() {}
-````
+```
 
 <figcaption>Listing 1-2: A write-up which <em>might</em> include inline Markdown like <code>code</code> etc.</figcaption>
 </figure>"#
@@ -40,19 +42,114 @@ fn main() {}
 ```
 
 </Listing>"#,
-        Mode::Simple,
+        Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert_eq!(
         &result.unwrap(),
-        r#"
-Filename: src/main.rs
+        r#"Filename: src/main.rs
+
+```rust
+fn main() {}
+```
+
+Listing 1-2: A write-up which *might* include inline Markdown like `code` etc."#
+    );
+}
+
+#[test]
+fn print_mode_works() {
+    let result = rewrite_listing(
+        r#"<Listing number="1-2" caption="A write-up which *might* include inline Markdown like `code` etc." file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config {
+            mode: Mode::Print,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#":::: {#listing-1-2 .listing number="1-2" file="src/main.rs"}
+
+```rust
+fn main() {}
+```
+
+::: caption
+A write-up which *might* include inline Markdown like `code` etc.
+:::
+::::"#
+    );
+}
+
+#[test]
+fn print_mode_without_number_or_caption() {
+    let result = rewrite_listing(
+        r#"<Listing file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config {
+            mode: Mode::Print,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#":::: {.listing file="src/main.rs"}
+
+```rust
+fn main() {}
+```
+
+::::"#
+    );
+}
+
+#[test]
+fn caption_which_looks_like_a_block() {
+    let result = rewrite_listing(
+        r#"<Listing number="1-2" caption="1. Setting *up*">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#"<figure class="listing" id="listing-1-2">
 
-````rust
+```rust
 fn main() {}
-````
+```
 
-Listing 1-2: A write-up which <em>might</em> include inline Markdown like <code>code</code> etc."#
+<figcaption>Listing 1-2: Setting <em>up</em></figcaption>
+</figure>"#
     );
 }
 
@@ -68,18 +165,20 @@ fn get_a_box_of<T>(t: T) -> Box<T> {
 ```
 
 </Listing>"#,
-        Mode::Default,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert_eq!(
         &result.unwrap(),
-        r#"<figure class="listing">
+        r#"<figure class="listing" id="listing-34-5">
 
-````rust
+```rust
 fn get_a_box_of<T>(t: T) -> Box<T> {
     Box::new(T)
 }
-````
+```
 
 <figcaption>Listing 34-5: This has a <code>Box&lt;T&gt;</code> in it.</figcaption>
 </figure>"#
@@ -102,7 +201,9 @@ fn main() {
 </Listing>
 
 Save the file and go back to your terminal window"#,
-        Mode::Default,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert!(result.is_ok());
@@ -110,14 +211,14 @@ Save the file and go back to your terminal window"#,
         result.unwrap(),
         r#"Now open the *main.rs* file you just created and enter the code in Listing 1-1.
 
-<figure class="listing">
+<figure class="listing" id="listing-1-1">
 <span class="file-name">Filename: main.rs</span>
 
-````rust
+```rust
 fn main() {
     println!("Hello, world!");
 }
-````
+```
 
 <figcaption>Listing 1-1: A program that prints <code>Hello, world!</code></figcaption>
 </figure>
@@ -140,7 +241,9 @@ fn main() {}
 </Listing>
 
 This is the closing."#,
-        Mode::Default,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert!(result.is_ok());
@@ -148,11 +251,11 @@ This is the closing."#,
         result.unwrap(),
         r#"This is the opening.
 
-<figure class="listing">
+<figure class="listing" id="listing-1-1">
 
-````rust
+```rust
 fn main() {}
-````
+```
 
 <figcaption>Listing 1-1: This is the caption</figcaption>
 </figure>
@@ -171,7 +274,9 @@ fn main() {}
 ```
 
 </Listing>"#,
-        Mode::Default,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
     );
 
     assert!(result.is_ok());
@@ -180,13 +285,598 @@ fn main() {}
         r#"<figure class="listing">
 <span class="file-name">Filename: src/main.rs</span>
 
-````rust
+```rust
+fn main() {}
+```
+
+</figure>"#
+    );
+}
+
+#[test]
+fn without_permalinks() {
+    let result = rewrite_listing(
+        r#"<Listing number="1-2" caption="Some caption">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config {
+            permalinks: false,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#"<figure class="listing" id="listing-1-2">
+
+```rust
+fn main() {}
+```
+
+<figcaption>Listing 1-2: Some caption</figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn with_permalinks() {
+    let result = rewrite_listing(
+        r#"<Listing number="1-2" caption="Some caption">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config {
+            permalinks: true,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r##"<figure class="listing" id="listing-1-2">
+
+```rust
+fn main() {}
+```
+
+<figcaption><a href="#listing-1-2">Listing 1-2</a>: Some caption</figcaption>
+</figure>"##
+    );
+}
+
+#[test]
+fn permalinks_in_simple_mode() {
+    let result = rewrite_listing(
+        r#"<Listing number="1-2" caption="Some caption">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config {
+            mode: Mode::Simple,
+            permalinks: true,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#"
+
+```rust
+fn main() {}
+```
+
+Listing 1-2: Some caption"#
+    );
+}
+
+#[test]
+fn numbers_listings_by_id() {
+    let book = book_with(vec![
+        (
+            2,
+            "ch02.md",
+            r#"<Listing number="2-1" caption="Numbered by hand">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing id="second" caption="Numbered automatically">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        ),
+        (
+            3,
+            "ch03.md",
+            r#"<Listing id="third" file-name="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        ),
+    ]);
+
+    let index = ListingIndex::build(&stage::parse(&book)).unwrap();
+    assert_eq!(index.get("second").unwrap().number, "2-2");
+    assert_eq!(index.get("third").unwrap().number, "3-1");
+
+    let result = rewrite_listing(
+        r#"<Listing id="second" caption="Numbered automatically">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &index,
+        ChapterInfo {
+            path: Some(Path::new("ch02.md")),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        &result.unwrap(),
+        r#"<figure class="listing" id="listing-2-2">
+
+```rust
 fn main() {}
-````
+```
 
+<figcaption>Listing 2-2: Numbered automatically</figcaption>
 </figure>"#
     );
 }
 
+#[test]
+fn resolves_references() {
+    let book = book_with(vec![
+        (
+            2,
+            "ch02.md",
+            r#"<Listing id="guess-input" caption="Getting a guess">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        ),
+        (3, "ch03.md", "See <ListingRef id=\"guess-input\" />."),
+    ]);
+    let index = ListingIndex::build(&stage::parse(&book)).unwrap();
+
+    let same_chapter = rewrite_listing(
+        r#"As shown in <ListingRef id="guess-input" />, we ask for input."#,
+        Config::default(),
+        &index,
+        ChapterInfo {
+            path: Some(Path::new("ch02.md")),
+            ..ChapterInfo::default()
+        },
+    );
+    assert_eq!(
+        same_chapter.unwrap(),
+        r##"As shown in <a href="#listing-2-1">Listing 2-1</a>, we ask for input."##
+    );
+
+    let other_chapter = rewrite_listing(
+        r#"As shown in <ListingRef id="guess-input" />, we ask for input."#,
+        Config::default(),
+        &index,
+        ChapterInfo {
+            path: Some(Path::new("ch03.md")),
+            ..ChapterInfo::default()
+        },
+    );
+    assert_eq!(
+        other_chapter.unwrap(),
+        r#"As shown in <a href="ch02.html#listing-2-1">Listing 2-1</a>, we ask for input."#
+    );
+
+    let simple = rewrite_listing(
+        r#"As shown in <ListingRef id="guess-input" />, we ask for input."#,
+        Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        },
+        &index,
+        ChapterInfo {
+            path: Some(Path::new("ch03.md")),
+            ..ChapterInfo::default()
+        },
+    );
+    assert_eq!(
+        simple.unwrap(),
+        "As shown in Listing 2-1, we ask for input."
+    );
+
+    let print = rewrite_listing(
+        r#"As shown in <ListingRef id="guess-input" />, we ask for input."#,
+        Config {
+            mode: Mode::Print,
+            ..Config::default()
+        },
+        &index,
+        ChapterInfo {
+            path: Some(Path::new("ch03.md")),
+            ..ChapterInfo::default()
+        },
+    );
+    assert_eq!(
+        print.unwrap(),
+        "As shown in [Listing 2-1](#listing-2-1), we ask for input."
+    );
+}
+
+#[test]
+fn unknown_reference() {
+    let result = rewrite_listing(
+        r#"As shown in <ListingRef id="nope" />, we ask for input."#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["line 1: Unknown listing id 'nope'"]
+    );
+}
+
+#[test]
+fn duplicate_ids() {
+    let listing = r#"<Listing id="twice">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#;
+    let book =
+        book_with(vec![(2, "ch02.md", listing), (3, "ch03.md", listing)]);
+
+    let errors = ListingIndex::build(&stage::parse(&book)).unwrap_err();
+    assert_eq!(
+        messages(errors),
+        vec![String::from(
+            "ch03.md:1: duplicate listing id 'twice' (already used by Listing 2-1)"
+        )]
+    );
+}
+
+#[test]
+fn unknown_attribute() {
+    let result = rewrite_listing(
+        r#"Some text.
+
+<Listing number="1-1" filename="src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            source_path: Some(Path::new("ch01.md")),
+            number: Some(1),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["ch01.md:3: unknown attribute 'filename' on <Listing>: expected one of `id`, `number`, `caption`, `file-name`, `src`, `anchor`, `highlight`, `dim`, `line-numbers`, `kind`, `for`, `compiles`, `panics`, or `playground`"]
+    );
+}
+
+#[test]
+fn invalid_number() {
+    let result = rewrite_listing(
+        r#"<Listing number="1.1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    );
+
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["line 1: invalid listing number '1.1': expected `<chapter>-<n>`, like `2-4`"]
+    );
+}
+
+#[test]
+fn number_from_other_chapter() {
+    let result = rewrite_listing(
+        r#"<Listing number="3-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            source_path: Some(Path::new("ch02.md")),
+            number: Some(2),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["ch02.md:1: listing number '3-1' does not match chapter 2"]
+    );
+}
+
+#[test]
+fn duplicate_numbers() {
+    let book = book_with(vec![(
+        2,
+        "ch02.md",
+        r#"<Listing number="2-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing number="2-1">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+    )]);
+
+    let errors = ListingIndex::build(&stage::parse(&book)).unwrap_err();
+    assert_eq!(
+        messages(errors),
+        vec![String::from(
+            "ch02.md:9: duplicate listing number '2-1' (already used at ch02.md:1)"
+        )]
+    );
+}
+
+#[test]
+fn list_of_listings() {
+    let book = book_with(vec![
+        (
+            2,
+            "ch02.md",
+            r#"<Listing number="2-1" file-name="src/main.rs" caption="A `main` | function">
+
+```rust
+fn main() {}
+```
+
+</Listing>
+
+<Listing file-name="src/lib.rs">
+
+```rust
+fn unnumbered() {}
+```
+
+</Listing>"#,
+        ),
+        (
+            3,
+            "ch03.md",
+            r#"<Listing id="third">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        ),
+    ]);
+
+    let index = ListingIndex::build(&stage::parse(&book)).unwrap();
+    assert_eq!(
+        index.list_of_listings("Listings", Path::new("appendix/listings.md")),
+        r#"# Listings
+
+| Listing | Caption | File name | Chapter |
+| ------- | ------- | --------- | ------- |
+| [Listing 2-1](../ch02.md#listing-2-1) | A `main` \| function | src/main.rs | [ch02.md](../ch02.md) |
+| [Listing 3-1](../ch03.md#listing-3-1) |  |  | [ch03.md](../ch03.md) |
+"#
+    );
+}
+
+#[test]
+fn listing_from_src() {
+    let result = rewrite_listing(
+        r#"<Listing number="2-1" file-name="src/main.rs" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs" anchor="here">
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        result.unwrap(),
+        r#"<figure class="listing" id="listing-2-1">
+<span class="file-name">Filename: src/main.rs</span>
+
+```rust
+# use std::io;
+# 
+# fn main() {
+    println!("Guess the number!");
+# }
+```
+
+<figcaption>Listing 2-1</figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn listing_from_src_with_other_language() {
+    let result = rewrite_listing(
+        r#"<Listing file-name="Cargo.toml" src="listings/ch02-guessing-game-tutorial/listing-02-01/Cargo.toml">
+</Listing>"#,
+        Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        result.unwrap(),
+        r#"Filename: Cargo.toml
+
+```toml
+[package]
+name = "guessing_game"
+version = "0.1.0"
+edition = "2021"
+```
+
+"#
+    );
+}
+
+#[test]
+fn listing_from_src_in_wrong_directory() {
+    let result = rewrite_listing(
+        r#"<Listing number="2-2" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs">
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["line 1: `src` 'listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs' for listing 2-2 should be in a `listing-02-02` directory, not `listing-02-01`"]
+    );
+}
+
+#[test]
+fn listing_from_src_with_code_block() {
+    let result = rewrite_listing(
+        r#"<Listing number="2-1" src="listings/ch02-guessing-game-tutorial/listing-02-01/src/main.rs">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#,
+        Config::default(),
+        &ListingIndex::default(),
+        ChapterInfo {
+            root: Some(Path::new(FIXTURES)),
+            ..ChapterInfo::default()
+        },
+    );
+
+    assert_eq!(
+        messages(result.unwrap_err()),
+        vec!["line 3: a listing with a `src` attribute cannot also contain a code block"]
+    );
+}
+
+/// Rewrite the listings in one chapter, the way the stage does.
+fn rewrite_listing(
+    src: &str,
+    config: Config,
+    index: &ListingIndex,
+    chapter: ChapterInfo<'_>,
+) -> Result<String, Vec<Diagnostic>> {
+    let edits = listing_edits(src, &events(src), config, index, chapter)?;
+    Ok(splice(src, edits))
+}
+
+/// The diagnostics as they appear in the preprocessor's error output.
+fn messages(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+    diagnostics.iter().map(ToString::to_string).collect()
+}
+
+const FIXTURES: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/fixtures");
+
+fn book_with(chapters: Vec<(u32, &str, &str)>) -> Book {
+    let mut book = Book::new();
+    for (number, path, content) in chapters {
+        let mut chapter =
+            Chapter::new(path, content.to_string(), path, Vec::new());
+        chapter.number = Some(SectionNumber(vec![number]));
+        book.push_item(chapter);
+    }
+    book
+}
+
 #[cfg(test)]
 mod config;
+
+#[cfg(test)]
+mod migrate;
+
+#[cfg(test)]
+mod diagnostic;
+
+#[cfg(test)]
+mod lines;
+
+#[cfg(test)]
+mod output;
+
+#[cfg(test)]
+mod files;
+
+#[cfg(test)]
+mod round_trip;
+
+#[cfg(test)]
+mod flags;
@@ -0,0 +1,169 @@
+//! Check output listings, which show the output of another listing.
+
+use std::path::Path;
+
+use trpl_preprocess::stage;
+
+use super::{book_with, messages, rewrite_listing};
+use crate::{ChapterInfo, Config, ListingIndex, Mode};
+
+const CODE: &str = r#"<Listing number="2-4" id="broken" file-name="src/main.rs">
+
+```rust,ignore,does_not_compile
+fn main() {
+    let x: u32 = "nope";
+}
+```
+
+</Listing>
+
+<Listing number="2-5">
+
+```rust
+fn main() {}
+```
+
+</Listing>"#;
+
+const OUTPUT: &str = r#"<Listing kind="output" for="2-4">
+
+```console
+$ cargo build
+error[E0308]: mismatched types
+```
+
+</Listing>"#;
+
+fn rewrite(src: &str, mode: Mode, path: &str) -> Result<String, Vec<String>> {
+    let book = book_with(vec![(2, "ch02.md", CODE), (3, "ch03.md", "")]);
+    let index = ListingIndex::build(&stage::parse(&book)).unwrap();
+    rewrite_listing(
+        src,
+        Config {
+            mode,
+            ..Config::default()
+        },
+        &index,
+        ChapterInfo {
+            path: Some(Path::new(path)),
+            ..ChapterInfo::default()
+        },
+    )
+    .map_err(messages)
+}
+
+#[test]
+fn output_of_code_which_does_not_compile() {
+    assert_eq!(
+        rewrite(OUTPUT, Mode::Default, "ch03.md").unwrap(),
+        r#"<figure class="listing output">
+<details>
+<summary>Compiler errors</summary>
+
+```console
+$ cargo build
+error[E0308]: mismatched types
+```
+
+</details>
+<figcaption>Output of <a href="ch02.html#listing-2-4">Listing 2-4</a></figcaption>
+</figure>"#
+    );
+}
+
+#[test]
+fn output_of_code_which_compiles() {
+    let src = r#"<Listing number="2-6" kind="output" for="2-5" caption="Running it">
+
+```console
+$ cargo run
+```
+
+</Listing>"#;
+
+    assert_eq!(
+        rewrite(src, Mode::Default, "ch02.md").unwrap(),
+        r##"<figure class="listing output" id="listing-2-6">
+
+```console
+$ cargo run
+```
+
+<figcaption>Listing 2-6: Output of <a href="#listing-2-5">Listing 2-5</a>: Running it</figcaption>
+</figure>"##
+    );
+}
+
+#[test]
+fn output_in_simple_mode() {
+    assert_eq!(
+        rewrite(OUTPUT, Mode::Simple, "ch03.md").unwrap(),
+        r#"
+
+```console
+$ cargo build
+error[E0308]: mismatched types
+```
+
+Output of Listing 2-4"#
+    );
+}
+
+#[test]
+fn output_in_print_mode() {
+    assert_eq!(
+        rewrite(OUTPUT, Mode::Print, "ch03.md").unwrap(),
+        r#":::: {.listing .output for="2-4"}
+
+```console
+$ cargo build
+error[E0308]: mismatched types
+```
+
+::: caption
+Output of [Listing 2-4](#listing-2-4)
+:::
+::::"#
+    );
+}
+
+#[test]
+fn output_for_an_id() {
+    let src = OUTPUT.replace(r#"for="2-4""#, r#"for="broken""#);
+    assert_eq!(
+        rewrite(&src, Mode::Default, "ch03.md").unwrap(),
+        rewrite(OUTPUT, Mode::Default, "ch03.md").unwrap()
+    );
+}
+
+#[test]
+fn invalid_output_listings() {
+    let cases = [
+        (
+            r#"<Listing kind="output" for="2-9">"#,
+            "line 1: `for` refers to Listing 2-9, which does not exist",
+        ),
+        (
+            r#"<Listing kind="output" for="nope">"#,
+            "line 1: Unknown listing id 'nope'",
+        ),
+        (
+            r#"<Listing for="2-4">"#,
+            "line 1: for attribute on a listing which is not `kind=\"output\"`",
+        ),
+        (
+            r#"<Listing kind="outptu">"#,
+            "line 1: invalid `kind` 'outptu': expected `code` or `output`",
+        ),
+    ];
+
+    for (tag, expected) in cases {
+        let src =
+            format!("{tag}\n\n```console\n$ cargo run\n```\n\n</Listing>");
+        assert_eq!(
+            rewrite(&src, Mode::Default, "ch03.md").unwrap_err(),
+            vec![expected],
+            "{tag}"
+        );
+    }
+}
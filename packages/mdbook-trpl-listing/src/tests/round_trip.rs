@@ -0,0 +1,94 @@
+//! Check that rewriting a chapter only touches its listings.
+
+use super::rewrite_listing;
+use crate::{ChapterInfo, Config, ListingIndex, Mode};
+
+/// Pieces of Markdown which re-rendering it would be likely to change: fence
+/// lengths, table alignment, escapes, entities, line endings, and so on, along
+/// with listing markup which is only an example in a code block.
+const FRAGMENTS: &[&str] = &[
+    "# A heading #\n",
+    "A heading\n=========\n",
+    "Some *emphasis*, __strong__, and `code`.\n",
+    "\"Straight quotes\" and 'apostrophes' -- and dashes...\n",
+    "An escaped \\*asterisk\\* and an &amp; entity.\n",
+    "Trailing spaces for a hard break  \nafter them.\n",
+    "Windows line endings\r\nin a paragraph.\r\n",
+    "```rust\nfn main() {}\n```\n",
+    "~~~\ntildes\n~~~\n",
+    "````markdown\n```rust\n```\n````\n",
+    "```text\n<Listing number=\"1-1\">\n</Listing>\n```\n",
+    "    indented code\n    block\n",
+    "| A | B |\n|:--|--:|\n|  1   | 2 |\n",
+    "* a list\n* with\n\n  a loose item\n",
+    "1) an ordered\n2) list\n",
+    "> a quote\n> > nested\n",
+    "<!-- a comment -->\n",
+    "<span class=\"caption\">Some HTML</span>\n",
+    "[a link][ref]\n\n[ref]: https://example.com \"title\"\n",
+    "A footnote[^1].\n\n[^1]: The note.\n",
+    "- [ ] a task\n",
+    "\t\ttabs\n",
+    "***\n",
+    "\n",
+    "\n\n\n",
+    "",
+];
+
+/// A tiny xorshift generator, so the test needs no dependencies and every run
+/// checks the same chapters.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+fn rewrite(src: &str, mode: Mode) -> String {
+    rewrite_listing(
+        src,
+        Config {
+            mode,
+            ..Config::default()
+        },
+        &ListingIndex::default(),
+        ChapterInfo::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn chapters_without_listings_are_unchanged() {
+    let mut rng = Rng(0x5eed);
+    for case in 0..500 {
+        let count = rng.next() % 12;
+        let src: String = (0..count)
+            .map(|_| FRAGMENTS[rng.next() % FRAGMENTS.len()])
+            .collect();
+
+        for mode in [Mode::Default, Mode::Simple, Mode::Print] {
+            assert_eq!(rewrite(&src, mode), src, "case {case}: {src:?}");
+        }
+    }
+}
+
+#[test]
+fn only_listing_tags_are_replaced() {
+    let before = "| A | B |\n|:--|--:|\n|  1   | 2 |\n\n";
+    let code = "```rust\nfn main() {}\n```\n";
+    let after = "\n\"Quotes\" -- stay\r\nas they were.\n";
+    let src = format!(
+        "{before}<Listing number=\"1-1\">\n\n{code}\n</Listing>\n{after}"
+    );
+
+    assert_eq!(
+        rewrite(&src, Mode::Default),
+        format!(
+            "{before}<figure class=\"listing\" id=\"listing-1-1\">\n\n{code}\n<figcaption>Listing 1-1</figcaption>\n</figure>\n{after}"
+        )
+    );
+}
@@ -0,0 +1,3 @@
+fn main() {
+    println!("Guess the number!");
+}
@@ -0,0 +1,65 @@
+use assert_cmd::Command;
+use trpl_preprocess::testing::{chapters, Fixture};
+
+#[test]
+fn supports_html_renderer() {
+    let cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["supports", "html"])
+        .ok();
+    assert!(cmd.is_ok());
+}
+
+#[test]
+fn errors_for_other_renderers() {
+    let cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["supports", "total-nonsense"])
+        .ok();
+    assert!(cmd.is_err());
+}
+
+/// Run the binary on the fixture book, through the same JSON `mdbook build`
+/// sends it, and compare what it sends back with the snapshots in
+/// `tests/fixtures/book/snapshots`. Run with `BLESS=1` to update them.
+#[test]
+fn preprocesses_a_book() {
+    Fixture::load("tests/fixtures/book").unwrap().assert_snapshots(
+        env!("CARGO_BIN_EXE_mdbook-trpl-listing"),
+        &["html", "markdown"],
+    );
+}
+
+/// `mdbook test` only runs the code blocks rustdoc sees as such, so listings
+/// which style their lines have to keep their fenced code blocks for it.
+#[test]
+fn keeps_highlighted_code_blocks_for_tests() {
+    let book = Fixture::load("tests/fixtures/book")
+        .unwrap()
+        .run_binary(env!("CARGO_BIN_EXE_mdbook-trpl-listing"), "test")
+        .unwrap();
+    let (_, content) = &chapters(&book)[1];
+    assert!(
+        content.contains(
+            "\n```rust\nuse std::io;\nfn main() {\n    let mut guess = String::new();\n}\n```\n"
+        ),
+        "{content}"
+    );
+    assert!(!content.contains("<pre>"), "{content}");
+}
+
+#[test]
+fn keeps_numbered_code_blocks_for_tests() {
+    let book = Fixture::load("tests/fixtures/book")
+        .unwrap()
+        .run_binary(env!("CARGO_BIN_EXE_mdbook-trpl-listing"), "test")
+        .unwrap();
+    let (_, content) = &chapters(&book)[1];
+    assert!(
+        content.contains(
+            "\n```rust\nfn main() {\n    println!(\"Guess the number!\");\n}\n```\n"
+        ),
+        "{content}"
+    );
+    assert!(!content.contains("data-line-number"), "{content}");
+}
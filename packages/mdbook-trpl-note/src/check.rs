@@ -0,0 +1,259 @@
+//! Find note-like markup which [`rewrite`](crate::rewrite) leaves alone, so
+//! that it can be fixed instead of quietly rendering as a plain blockquote.
+
+use std::ops::Range;
+
+use pulldown_cmark::{Event::*, Tag, TagEnd};
+use trpl_preprocess::rewrite::events;
+
+use crate::{is_blank, notes, unquote, Config};
+
+/// Something in a chapter which looks like a note but will not become one.
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    /// The 1-based line in the source.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Check a chapter's source for note-like markup which `config` does not turn
+/// into notes:
+///
+/// - blockquotes which start with a kind's label in bold or emphasis, like
+///   `> **Note:**`, or with the label in a different case, like `> NOTE: `;
+/// - alert markers for kinds it does not know, or with more after them on the
+///   line than a collapsible note's title;
+/// - blockquotes which follow a note with only blank lines between them, which
+///   were probably meant to be part of it;
+/// - paragraphs outside of any blockquote which start with a kind's prefix.
+pub fn check(src: &str, config: &Config) -> Vec<Finding> {
+    let events = events(src);
+    let notes: Vec<Range<usize>> = notes(src, &events, config)
+        .into_iter()
+        .map(|note| note.span)
+        .collect();
+
+    let mut findings = vec![];
+    let mut found = |offset: usize, message: String| {
+        findings.push(Finding {
+            line: src[..offset].matches('\n').count() + 1,
+            message,
+        });
+    };
+
+    let mut quotes = 0;
+    let mut note_end = None;
+    let mut events = events.into_iter().peekable();
+    while let Some((event, range)) = events.next() {
+        match event {
+            Start(Tag::BlockQuote) => {
+                quotes += 1;
+                if notes.contains(&range) {
+                    note_end = Some(range.end);
+                    continue;
+                }
+
+                if let Some(end) = note_end.take() {
+                    if is_blank(&src[end.min(range.start)..range.start]) {
+                        found(
+                            range.start,
+                            String::from(
+                                "blockquote after a note is not part of it; join them with a `>` line",
+                            ),
+                        );
+                        continue;
+                    }
+                }
+
+                if !matches!(events.peek(), Some((Start(Tag::Paragraph), _))) {
+                    continue;
+                }
+                events.next();
+
+                match events.peek() {
+                    Some((Start(Tag::Strong | Tag::Emphasis), _)) => {
+                        events.next();
+                        if let Some((Text(text), _)) = events.peek() {
+                            if let Some(prefix) = label_prefix(config, text) {
+                                found(
+                                    range.start,
+                                    format!(
+                                        "blockquote starting with `{}` in bold or emphasis is not a note; start it with plain `{}` instead",
+                                        text.trim(),
+                                        prefix.trim_end()
+                                    ),
+                                );
+                            }
+                        }
+                    }
+
+                    Some((Text(text), _)) if text.as_ref() == "[" => {
+                        let line = src[range.start..]
+                            .lines()
+                            .next()
+                            .unwrap_or_default();
+                        let line = unquote(line).unwrap_or(line).trim_end();
+                        let Some(marker) = line
+                            .strip_prefix("[!")
+                            .and_then(|rest| rest.split(']').next())
+                        else {
+                            continue;
+                        };
+                        let message = if config
+                            .alert_kind(&format!("[!{marker}]"))
+                            .is_none()
+                        {
+                            format!("`[!{marker}]` is not a kind of note")
+                        } else {
+                            format!(
+                                "`{line}` is not an alert: the marker needs a line of its own, or `-` or `+` and a title after it"
+                            )
+                        };
+                        found(range.start, message);
+                    }
+
+                    Some((Text(text), _)) => {
+                        if let Some(prefix) = config
+                            .kinds
+                            .iter()
+                            .map(|kind| kind.prefix.as_str())
+                            .find(|prefix| {
+                                starts_with_ignore_case(text, prefix)
+                            })
+                        {
+                            found(
+                                range.start,
+                                format!(
+                                    "`{}` is not a note; write it as `{}`",
+                                    &text[..prefix.len()].trim_end(),
+                                    prefix.trim_end()
+                                ),
+                            );
+                        }
+                    }
+
+                    _ => {}
+                }
+            }
+
+            End(TagEnd::BlockQuote) => quotes -= 1,
+
+            Start(Tag::Paragraph) if quotes == 0 => {
+                let kind = match events.peek() {
+                    Some((Text(text), _)) => config
+                        .kinds
+                        .iter()
+                        .find(|kind| text.starts_with(kind.prefix.as_str())),
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    found(
+                        range.start,
+                        format!(
+                            "paragraph starting with `{}` is not a note; put it in a blockquote",
+                            kind.prefix.trim_end()
+                        ),
+                    );
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    findings
+}
+
+/// The prefix of the kind whose label `text` starts with, with or without a
+/// colon, as in `**Note:**` or `**Note**:`.
+fn label_prefix<'c>(config: &'c Config, text: &str) -> Option<&'c str> {
+    config
+        .kinds
+        .iter()
+        .find(|kind| starts_with_ignore_case(text, kind.label()))
+        .map(|kind| kind.prefix.as_str())
+}
+
+/// Whether `text` starts with `prefix`, in any case.
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(src: &str) -> Vec<String> {
+        check(src, &Config::default())
+            .into_iter()
+            .map(|Finding { line, message }| format!("line {line}: {message}"))
+            .collect()
+    }
+
+    #[test]
+    fn notes_are_fine() {
+        let src = "> Note: A note.\n\n> [!TIP]\n> A tip.\n\n> [!WARNING]- A title\n> Text.\n\n> ## Heading\n> Text.\n\nA paragraph about notes.\n\n> A quote.\n";
+        assert_eq!(messages(src), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unconverted_notes() {
+        let src = r#"> **Note:** In bold.
+
+> *Warning*: In emphasis.
+
+> NOTE: Shouting.
+
+> [!CAUTION]
+> Not a kind.
+
+> [!NOTE] More on the line.
+
+Note: Not in a blockquote.
+"#;
+        assert_eq!(
+            messages(src),
+            vec![
+                "line 1: blockquote starting with `Note:` in bold or emphasis is not a note; start it with plain `Note:` instead",
+                "line 3: blockquote starting with `Warning` in bold or emphasis is not a note; start it with plain `Warning:` instead",
+                "line 5: `NOTE:` is not a note; write it as `Note:`",
+                "line 7: `[!CAUTION]` is not a kind of note",
+                "line 10: `[!NOTE] More on the line.` is not an alert: the marker needs a line of its own, or `-` or `+` and a title after it",
+                "line 12: paragraph starting with `Note:` is not a note; put it in a blockquote",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_notes() {
+        let src = "> Note: The start of a note.\n\n> The rest of it.\n\n> > Note: Nested.\n>\n> > The rest.\n";
+        assert_eq!(
+            messages(src),
+            vec![
+                "line 3: blockquote after a note is not part of it; join them with a `>` line",
+                "line 7: blockquote after a note is not part of it; join them with a `>` line",
+            ]
+        );
+
+        // Anything else in between means they were meant to be separate.
+        let src = "> Note: A note.\n\nA paragraph.\n\n> A quote.\n";
+        assert_eq!(messages(src), Vec::<String>::new());
+    }
+
+    #[test]
+    fn only_configured_kinds() {
+        let config = Config {
+            kinds: Config::default()
+                .kinds
+                .into_iter()
+                .filter(|kind| kind.name == "tip")
+                .collect(),
+            ..Config::default()
+        };
+        let findings =
+            check("> **Note:** Not checked.\n\n> **Tip:** Checked.\n", &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].line, 3);
+    }
+}
@@ -1,15 +1,21 @@
+use std::ops::Range;
+
 use mdbook::{
     book::Book,
     errors::Result,
     preprocess::{Preprocessor, PreprocessorContext},
-    utils::new_cmark_parser,
-    BookItem,
 };
-use pulldown_cmark::{
-    Event::{self, *},
-    Tag, TagEnd,
+use pulldown_cmark::{Event, Event::*, Tag, TagEnd};
+use trpl_preprocess::{
+    config::{self, ConfigTable},
+    rewrite::{events, inline_html, splice, trim_end, Edit},
+    stage::{self, Changes, Parsed, Stage},
+    TrplPreprocessor,
 };
-use pulldown_cmark_to_cmark::cmark;
+
+pub use trpl_preprocess::Mode;
+
+pub mod check;
 
 /// A simple preprocessor for semantic notes in _The Rust Programming Language_.
 ///
@@ -28,122 +34,687 @@ use pulldown_cmark_to_cmark::cmark;
 ///
 /// </section>
 /// ```
+///
+/// Besides notes, it recognizes a few other kinds of callout, each by the
+/// prefix the blockquote starts with:
+///
+/// | Kind         | Prefix              | Class               | Role         |
+/// |--------------|---------------------|---------------------|--------------|
+/// | `note`       | `Note: `            | `note`              | `note`       |
+/// | `warning`    | `Warning: `         | `note warning`      | `doc-notice` |
+/// | `tip`        | `Tip: `             | `note tip`          | `doc-tip`    |
+/// | `historical` | `Historical Note: ` | `note historical`   | `note`       |
+/// | `edition`    | `Edition Note: `    | `note edition`      | `note`       |
+///
+/// GitHub's alert syntax works too, so that the Markdown previews the same way
+/// on GitHub: a blockquote whose first line is only `[!WARNING]`, or any other
+/// kind's name in capitals, becomes that kind of callout, without the marker.
+///
+/// An alert marker followed by `-` or `+` makes a note which can be collapsed,
+/// as a `<details>` element which starts out collapsed or expanded, and the
+/// rest of the line is its title:
+///
+/// ```markdown
+/// > [!NOTE]- How Cargo finds the crate
+/// > A long aside.
+/// ```
+///
+/// A blockquote which starts with a heading is a `note`, and one whose heading
+/// has the `collapsed` class, like `## Editions {.collapsed}`, is collapsed,
+/// with the heading as its title. The other output modes make them ordinary
+/// notes, with an alert's title as a bold lead-in.
+///
+/// All of the kinds are recognized unless `[preprocessor.trpl-note]` picks some
+/// with `kinds`, and any of them can be changed, or new ones added, with a
+/// table for the kind:
+///
+/// ```toml
+/// [preprocessor.trpl-note]
+/// kinds = ["note", "warning", "edition"]
+///
+/// [preprocessor.trpl-note.kind.edition]
+/// prefix = "Rust 2024: "
+/// ```
+///
+/// A new kind needs all of `prefix`, `class`, and `role`.
+///
+/// For renderers which do not keep HTML, `output-mode = "simple"` instead sets
+/// each note off with horizontal rules, and makes its prefix a bold lead-in:
+///
+/// ```markdown
+/// ---
+///
+/// **Note:** This is a note.
+///
+/// ---
+/// ```
+///
+/// `output-mode = "print"` wraps it in a Pandoc fenced div, like
+/// `::: {.note .warning}`. As with `trpl-listing`, `output-mode` can also be a
+/// table of renderer names to modes.
+///
+/// Notes can be in list items or other blockquotes, and can have blockquotes
+/// in them. Only the notes are rewritten: the rest of the chapter is left
+/// exactly as it was.
 pub struct TrplNote;
 
 impl Preprocessor for TrplNote {
     fn name(&self) -> &str {
-        "simple-note-preprocessor"
+        "trpl-note"
     }
 
     fn run(
         &self,
-        _ctx: &PreprocessorContext,
-        mut book: Book,
+        ctx: &PreprocessorContext,
+        book: Book,
     ) -> Result<mdbook::book::Book> {
-        book.for_each_mut(|item| {
-            if let BookItem::Chapter(ref mut chapter) = item {
-                chapter.content = rewrite(&chapter.content);
-            }
-        });
-        Ok(book)
+        let config = ConfigTable::from_context(ctx, self.name());
+        stage::run(ctx, book, &[("note", self, config)])
     }
 
     fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html" || renderer == "markdown" || renderer == "test"
+        trpl_preprocess::supports_renderer(renderer)
+    }
+}
+
+impl TrplPreprocessor for TrplNote {}
+
+impl Stage for TrplNote {
+    fn rewrite(
+        &self,
+        _ctx: &PreprocessorContext,
+        config: Option<ConfigTable<'_>>,
+        chapters: &[Parsed<'_>],
+    ) -> Result<Changes> {
+        let config = match config {
+            Some(table) => Config::from_table(table)?,
+            None => Config::default(),
+        };
+
+        let edits = chapters
+            .iter()
+            .map(|chapter| {
+                let src = chapter.src();
+                notes(src, &chapter.events, &config)
+                    .into_iter()
+                    .map(|note| note.rewrite(src, config.mode))
+                    .collect()
+            })
+            .collect();
+        Ok(Changes {
+            edits,
+            chapters: vec![],
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error(transparent)]
+    Config(#[from] config::Error),
+
+    #[error("Unknown kind of note '{0}'")]
+    UnknownKind(String),
+
+    #[error("Kind of note '{kind}' needs a value for '{field}'")]
+    MissingField { kind: String, field: &'static str },
+}
+
+/// How to rewrite notes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub mode: Mode,
+    /// The kinds of callout to recognize, in the order they are tried.
+    pub kinds: Vec<Kind>,
+}
+
+impl std::default::Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::Default,
+            kinds: Kind::built_in(),
+        }
+    }
+}
+
+impl Config {
+    /// Read the `[preprocessor.trpl-note]` table from a book's `config`, the
+    /// same way the preprocessor does when it runs for `renderer`.
+    pub fn from_book(
+        config: &mdbook::Config,
+        renderer: &str,
+    ) -> Result<Config> {
+        match config.get_preprocessor(TrplNote.name()) {
+            Some(table) => {
+                Ok(Config::from_table(ConfigTable::new(table, renderer))?)
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Read the `[preprocessor.trpl-note]` table.
+    fn from_table(
+        table: ConfigTable<'_>,
+    ) -> std::result::Result<Config, Error> {
+        let mode = table.mode()?;
+        let mut kinds = Kind::built_in();
+
+        if let Some(value) = table.get("kind") {
+            let bad_value = || config::Error::bad_value("kind", value);
+            for (name, fields) in value.as_table().ok_or_else(bad_value)? {
+                let fields = fields.as_table().ok_or_else(bad_value)?;
+                let field = |field: &'static str| {
+                    fields
+                        .get(field)
+                        .map(|value| {
+                            value.as_str().map(String::from).ok_or_else(|| {
+                                config::Error::bad_value(
+                                    format!("kind.{name}.{field}"),
+                                    value,
+                                )
+                            })
+                        })
+                        .transpose()
+                };
+                let (prefix, class, role) =
+                    (field("prefix")?, field("class")?, field("role")?);
+
+                match kinds.iter_mut().find(|kind| kind.name == *name) {
+                    Some(kind) => {
+                        kind.prefix = prefix.unwrap_or(kind.prefix.clone());
+                        kind.class = class.unwrap_or(kind.class.clone());
+                        kind.role = role.unwrap_or(kind.role.clone());
+                    }
+                    None => {
+                        let missing = |field| Error::MissingField {
+                            kind: name.clone(),
+                            field,
+                        };
+                        kinds.push(Kind {
+                            name: name.clone(),
+                            prefix: prefix.ok_or_else(|| missing("prefix"))?,
+                            class: class.ok_or_else(|| missing("class"))?,
+                            role: role.ok_or_else(|| missing("role"))?,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(value) = table.get("kinds") {
+            let bad_value = || config::Error::bad_value("kinds", value);
+            let mut selected = vec![];
+            for name in value.as_array().ok_or_else(bad_value)? {
+                let name = name.as_str().ok_or_else(bad_value)?;
+                let kind = kinds
+                    .iter()
+                    .find(|kind| kind.name == name)
+                    .ok_or_else(|| Error::UnknownKind(name.to_string()))?;
+                selected.push(kind.clone());
+            }
+            kinds = selected;
+        }
+
+        Ok(Config { mode, kinds })
+    }
+
+    /// The kind a GitHub alert marker like `[!WARNING]` is for, if any. The
+    /// name in it is the kind's name, in any case.
+    fn alert_kind(&self, marker: &str) -> Option<&Kind> {
+        let name = marker.strip_prefix("[!")?.strip_suffix(']')?;
+        self.kinds
+            .iter()
+            .find(|kind| kind.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The kind of the alert whose first line is `line`, and whether it can be
+    /// collapsed. The marker can only be followed by `-` or `+`, for a note
+    /// which starts out collapsed or expanded, and a title for it.
+    fn alert(&self, line: &str) -> Option<(&Kind, Option<Fold>)> {
+        let line = line.trim_end();
+        let end = line.find(']')? + 1;
+        let kind = self.alert_kind(&line[..end])?;
+        let rest = &line[end..];
+        let fold = match rest.chars().next() {
+            None => None,
+            Some(c @ ('-' | '+')) => Some(Fold {
+                title: rest[1..].trim().to_string(),
+                open: c == '+',
+            }),
+            Some(_) => return None,
+        };
+        Some((kind, fold))
+    }
+}
+
+/// A kind of callout, which a blockquote is when its text starts with the
+/// kind's prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kind {
+    /// What it is called in the configuration, like `warning`.
+    pub name: String,
+    /// The text which marks a blockquote as this kind, like `Warning: `.
+    pub prefix: String,
+    /// The class of the `<section>` it becomes.
+    pub class: String,
+    /// The ARIA role of the `<section>` it becomes.
+    pub role: String,
+}
+
+impl Kind {
+    /// The kinds of callout the book uses.
+    pub fn built_in() -> Vec<Kind> {
+        [
+            ("note", "Note: ", "note", "note"),
+            ("warning", "Warning: ", "note warning", "doc-notice"),
+            ("tip", "Tip: ", "note tip", "doc-tip"),
+            ("historical", "Historical Note: ", "note historical", "note"),
+            ("edition", "Edition Note: ", "note edition", "note"),
+        ]
+        .into_iter()
+        .map(|(name, prefix, class, role)| Kind {
+            name: name.into(),
+            prefix: prefix.into(),
+            class: class.into(),
+            role: role.into(),
+        })
+        .collect()
+    }
+
+    /// What the kind is called in the text, like `Historical Note`.
+    fn label(&self) -> &str {
+        self.prefix.trim_end().trim_end_matches(':')
+    }
+
+    /// The line which starts a collapsible note of this kind, expanded to
+    /// begin with if `open`.
+    fn details(&self, open: bool) -> String {
+        format!(
+            r#"<details class="{}" aria-role="{}"{}>"#,
+            self.class,
+            self.role,
+            if open { " open" } else { "" }
+        )
+    }
+
+    /// The line which starts this kind of note.
+    fn opening(&self, mode: Mode) -> String {
+        match mode {
+            Mode::Default => format!(
+                r#"<section class="{}" aria-role="{}">"#,
+                self.class, self.role
+            ),
+            Mode::Simple => String::from("---"),
+            Mode::Print => {
+                let classes: Vec<_> = self
+                    .class
+                    .split_whitespace()
+                    .map(|class| format!(".{class}"))
+                    .collect();
+                format!("::: {{{}}}", classes.join(" "))
+            }
+        }
     }
 }
 
+/// The line which ends a note.
+fn closing(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Default => "</section>",
+        Mode::Simple => "---",
+        Mode::Print => ":::",
+    }
+}
+
+/// Rewrite notes in `text` with the default configuration.
 pub fn rewrite(text: &str) -> String {
-    let parser = new_cmark_parser(text, true);
+    rewrite_with(text, &Config::default())
+}
+
+/// Rewrite notes in `text`, wherever they are: at the top level, in a list
+/// item, or in another blockquote. Only the notes are rewritten, and every byte
+/// outside of them is kept exactly as it was.
+pub fn rewrite_with(text: &str, config: &Config) -> String {
+    let edits = notes(text, &events(text), config)
+        .into_iter()
+        .map(|note| note.rewrite(text, config.mode));
+    splice(text, edits)
+}
 
-    let mut events = Vec::new();
+/// The blockquotes in `text`, which has `events`, which are notes, in order.
+fn notes<'c>(
+    text: &str,
+    events: &[(Event<'_>, Range<usize>)],
+    config: &'c Config,
+) -> Vec<Note<'c>> {
+    let mut notes = vec![];
     let mut state = Default;
 
-    for event in parser {
+    for (event, range) in events.iter().cloned() {
         match (&mut state, event) {
-            (Default, Start(Tag::BlockQuote)) => {
-                state = StartingBlockquote(vec![Start(Tag::BlockQuote)]);
+            // A blockquote inside one which is not a note can still be one.
+            (Default | StartingBlockquote(_), Start(Tag::BlockQuote)) => {
+                state = StartingBlockquote(range);
             }
 
-            (StartingBlockquote(blockquote_events), Text(content)) => {
-                if content.starts_with("Note: ") {
-                    // This needs the "extra" `SoftBreak`s so that when the final rendering pass
-                    // happens, it does not end up treating the internal content as inline *or*
-                    // treating the HTML tags as inline tags:
-                    //
-                    // - Content inside HTML blocks is only rendered as Markdown when it is
-                    //   separated from the block HTML elements: otherwise it gets treated as inline
-                    //   HTML and *not* rendered.
-                    // - Along the same lines, an HTML tag that happens to be directly adjacent to
-                    //   the end of a previous Markdown block will end up being rendered as part of
-                    //   that block.
-                    events.extend([
-                        SoftBreak,
-                        SoftBreak,
-                        Html(
-                            r#"<section class="note" aria-role="note">"#.into(),
-                        ),
-                        SoftBreak,
-                        SoftBreak,
-                        Start(Tag::Paragraph),
-                        Text(content),
-                    ]);
-                    state = InNote;
-                } else {
-                    events.append(blockquote_events);
-                    events.push(Text(content));
-                    state = Default;
-                }
+            (StartingBlockquote(_), Start(Tag::Paragraph)) => {}
+
+            // GitHub's alert syntax, `> [!NOTE]`, comes through as separate
+            // text for the brackets and the name between them.
+            (StartingBlockquote(quote), Text(content))
+                if content.as_ref() == "[" =>
+            {
+                state = StartingAlert(quote.clone(), false);
             }
 
+            (StartingAlert(_, closed @ false), Text(content)) => {
+                *closed = content.contains(']');
+            }
+
+            // The whole line is checked once it ends, from the source, since
+            // a title after the marker can have any Markdown in it.
             (
-                StartingBlockquote(_blockquote_events),
-                heading @ Start(Tag::Heading { .. }),
+                StartingAlert(quote, true),
+                SoftBreak | HardBreak | End(TagEnd::Paragraph),
             ) => {
-                events.extend([
-                    SoftBreak,
-                    SoftBreak,
-                    Html(r#"<section class="note" aria-role="note">"#.into()),
-                    SoftBreak,
-                    SoftBreak,
-                    heading,
-                ]);
-                state = InNote;
+                let line =
+                    text[quote.start..].lines().next().unwrap_or_default();
+                state = match config.alert(unquote(line).unwrap_or(line)) {
+                    Some((kind, fold)) => InNote(Note {
+                        fold,
+                        ..Note::new(quote, kind, Lead::Alert)
+                    }),
+                    None => Default,
+                };
             }
 
-            (StartingBlockquote(ref mut events), Start(tag)) => {
-                events.push(Start(tag));
+            (StartingAlert(_, true), _) => {}
+
+            (StartingBlockquote(quote), Text(content)) => {
+                let kind = config
+                    .kinds
+                    .iter()
+                    .find(|kind| content.starts_with(kind.prefix.as_str()));
+                state = match kind {
+                    Some(kind) => InNote(Note::new(
+                        quote,
+                        kind,
+                        Lead::Prefix(range.start),
+                    )),
+                    None => Default,
+                };
             }
 
-            (InNote, End(TagEnd::BlockQuote)) => {
-                // As with the start of the block HTML, the closing HTML must be
-                // separated from the Markdown text by two newlines.
-                events.extend([
-                    SoftBreak,
-                    SoftBreak,
-                    Html("</section>".into()),
-                ]);
+            // A heading with the `collapsed` class, like
+            // `## Details {.collapsed}`, is the title of a collapsed note.
+            (
+                StartingBlockquote(quote),
+                Start(Tag::Heading { classes, .. }),
+            ) => {
+                state = match config
+                    .kinds
+                    .iter()
+                    .find(|kind| kind.name == "note")
+                {
+                    Some(kind) => {
+                        let fold = classes
+                            .iter()
+                            .any(|class| class.as_ref() == "collapsed")
+                            .then(|| Fold {
+                                title: heading_title(&text[range.clone()])
+                                    .to_string(),
+                                open: false,
+                            });
+                        InNote(Note {
+                            fold,
+                            ..Note::new(quote, kind, Lead::Heading(range))
+                        })
+                    }
+                    None => Default,
+                };
+            }
+
+            (StartingBlockquote(_) | StartingAlert(..), _) => {
                 state = Default;
             }
 
-            (_, event) => {
-                events.push(event);
+            // Blockquotes inside a note are part of it, so only the end of the
+            // note's own blockquote ends it.
+            (InNote(note), Start(Tag::BlockQuote)) => {
+                note.depth += 1;
+            }
+
+            (InNote(note), End(TagEnd::BlockQuote)) if note.depth > 0 => {
+                note.depth -= 1;
+            }
+
+            (InNote(_), End(TagEnd::BlockQuote)) => {
+                if let InNote(note) = std::mem::replace(&mut state, Default) {
+                    notes.push(note);
+                }
             }
+
+            _ => {}
         }
     }
 
-    let mut buf = String::new();
-    cmark(events.into_iter(), &mut buf).unwrap();
-    buf
+    notes
 }
 
 use State::*;
 
 #[derive(Debug)]
-enum State<'e> {
+enum State<'c> {
     Default,
-    StartingBlockquote(Vec<Event<'e>>),
-    InNote,
+    /// In a blockquote which may be a note, at this span of the source.
+    StartingBlockquote(Range<usize>),
+    /// Possibly in a GitHub alert marker like `[!NOTE]`: the blockquote's span,
+    /// and whether the marker's `]` has come yet.
+    StartingAlert(Range<usize>, bool),
+    InNote(Note<'c>),
+}
+
+/// A blockquote which is a note.
+#[derive(Debug)]
+struct Note<'c> {
+    /// The whole blockquote in the source, `>` markers and all.
+    span: Range<usize>,
+    kind: &'c Kind,
+    lead: Lead,
+    /// How the note can be collapsed, if it can.
+    fold: Option<Fold>,
+    /// How many blockquotes inside the note the parser is in.
+    depth: usize,
+}
+
+/// What marks a blockquote as a note.
+#[derive(Debug)]
+enum Lead {
+    /// Its text starts with the kind's prefix, at this offset in the source.
+    Prefix(usize),
+    /// It starts with a heading, at this span of the source.
+    Heading(Range<usize>),
+    /// Its first line is a GitHub alert marker, which is dropped.
+    Alert,
+}
+
+/// A note which can be collapsed, as a `<details>` element.
+#[derive(Debug)]
+struct Fold {
+    /// The Markdown for its `<summary>`, which may be empty.
+    title: String,
+    /// Whether it starts out expanded.
+    open: bool,
+}
+
+impl<'c> Note<'c> {
+    fn new(span: &Range<usize>, kind: &'c Kind, lead: Lead) -> Note<'c> {
+        Note {
+            span: span.clone(),
+            kind,
+            lead,
+            fold: None,
+            depth: 0,
+        }
+    }
+
+    /// The blockquote in `src` without its `>` markers, between the lines
+    /// which open and close the note.
+    fn rewrite(&self, src: &str, mode: Mode) -> Edit {
+        let span = trim_end(src, self.span.clone());
+        let line_start = src[..span.start].rfind('\n').map_or(0, |n| n + 1);
+
+        // Whatever the note is nested in, like a list item or another
+        // blockquote, comes before it on its first line. The lines added for
+        // the note need the same, with list markers turned into spaces, to
+        // stay in it.
+        let outer = &src[line_start..span.start];
+        let indent: String = outer
+            .chars()
+            .map(|c| {
+                if c == '>' || c.is_whitespace() {
+                    c
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+        let blank = indent.trim_end();
+
+        // Only HTML can collapse, so the other modes make a plain note of it.
+        let fold = self.fold.as_ref().filter(|_| mode == Mode::Default);
+
+        let mut body = src[span.clone()].to_string();
+        if let (Mode::Simple, &Lead::Prefix(start)) = (mode, &self.lead) {
+            let label = self.kind.prefix.trim_end();
+            let at = start - span.start;
+            body.replace_range(at..at + label.len(), &format!("**{label}**"));
+        }
+
+        let lines = body.split('\n').enumerate().map(|(index, line)| {
+            if index == 0 {
+                return format!("{indent}{}", unquote(line).unwrap_or(line));
+            }
+            let unquoted = line
+                .get(..outer.len())
+                .zip(line.get(outer.len()..).and_then(unquote));
+            match unquoted {
+                Some((outer, rest)) => format!("{outer}{rest}"),
+                // A lazy continuation line, which has no `>` to take out.
+                None => line.to_string(),
+            }
+        });
+        // The lines which mark the note are dropped, along with any blank
+        // lines after them: an alert marker, and the heading which is the
+        // summary of a collapsed note.
+        let marker_lines = match (&self.lead, fold) {
+            (Lead::Alert, _) => 1,
+            (Lead::Heading(heading), Some(_)) => src
+                [span.start..trim_end(src, heading.clone()).end]
+                .lines()
+                .count(),
+            (Lead::Prefix(_) | Lead::Heading(_), _) => 0,
+        };
+        let lines: Vec<String> = lines
+            .skip(marker_lines)
+            .skip_while(|line| marker_lines > 0 && is_blank(line))
+            .collect();
+
+        // The opening and closing lines have to be blocks of their own, with
+        // blank lines around them: otherwise an HTML tag ends up inline in the
+        // Markdown next to it, or the Markdown inside it is not rendered, and
+        // a `---` right after a paragraph makes it a heading instead of a
+        // rule.
+        let mut text = String::new();
+        let before = src[..line_start].strip_suffix('\n').unwrap_or_default();
+        let previous = before.rsplit('\n').next().unwrap_or_default();
+        if !is_blank(outer) || !is_blank(previous) {
+            text.push('\n');
+            text.push_str(&indent);
+        }
+        match fold {
+            Some(fold) => {
+                text.push_str(&self.kind.details(fold.open));
+                let title = match fold.title.as_str() {
+                    "" => self.kind.label(),
+                    title => title,
+                };
+                text.push_str(&format!(
+                    "\n{indent}<summary>{}</summary>",
+                    inline_html(title)
+                ));
+            }
+            None => text.push_str(&self.kind.opening(mode)),
+        }
+        text.push('\n');
+        text.push_str(blank);
+        text.push('\n');
+
+        // The marker stands in for the prefix, so in the simple mode it
+        // becomes a lead-in of its own, the way GitHub shows it. An alert's
+        // title is part of its text, so it always becomes one when the note
+        // cannot collapse.
+        let title = self.fold.as_ref().map(|fold| fold.title.as_str());
+        let lead_in = match (mode, &self.lead, title) {
+            (Mode::Default, _, _)
+            | (_, Lead::Prefix(_) | Lead::Heading(_), _) => None,
+            (_, Lead::Alert, Some(title)) if !title.is_empty() => Some(title),
+            (Mode::Simple, Lead::Alert, _) => Some(self.kind.label()),
+            (Mode::Print, Lead::Alert, _) => None,
+        };
+        if let Some(lead_in) = lead_in {
+            text.push_str(&format!("{indent}**{lead_in}**\n{blank}\n"));
+        }
+
+        for line in lines {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(blank);
+        text.push('\n');
+        text.push_str(&indent);
+        text.push_str(match fold {
+            Some(_) => "</details>",
+            None => closing(mode),
+        });
+
+        let next = src[span.end..].split('\n').nth(1);
+        if next.is_some_and(|line| !is_blank(line)) {
+            text.push('\n');
+            text.push_str(blank);
+        }
+
+        Edit { span, text }
+    }
+}
+
+/// `line` without the `>` which marks it as part of a blockquote, or the space
+/// after it, if it has one.
+fn unquote(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let rest = trimmed.strip_prefix('>')?;
+    Some(rest.strip_prefix([' ', '\t']).unwrap_or(rest))
+}
+
+/// The text of the heading at `src`, without its `#`s or attributes.
+fn heading_title(src: &str) -> &str {
+    let line = src.lines().next().unwrap_or_default().trim();
+    let line = match line.rfind('{') {
+        Some(start) if line.ends_with('}') => &line[..start],
+        _ => line,
+    };
+    line.trim_start_matches('#')
+        .trim_end()
+        .trim_end_matches('#')
+        .trim()
+}
+
+/// Whether `line` has nothing in it besides whitespace and blockquote markers.
+fn is_blank(line: &str) -> bool {
+    line.chars().all(|c| c == '>' || c.is_whitespace())
 }
 
 #[cfg(test)]
@@ -307,11 +878,7 @@ mod tests {
         let text = "| Header 1 | Header 2 |\n| -------- | -------- |\n| Text 123 | More 456 |";
         let processed = rewrite(text);
 
-        assert_eq!(
-            processed,
-            "|Header 1|Header 2|\n|--------|--------|\n|Text 123|More 456|",
-            "It strips some whitespace but otherwise leaves the table intact."
-        );
+        assert_eq!(processed, text, "It leaves the table exactly as it was.");
     }
 
     #[test]
@@ -321,7 +888,7 @@ mod tests {
 
         assert_eq!(
             processed,
-            "\n\n<section class=\"note\" aria-role=\"note\">\n\nNote: table stuff.\n\n</section>\n\n|Header 1|Header 2|\n|--------|--------|\n|Text 123|More 456|",
+            "<section class=\"note\" aria-role=\"note\">\n\nNote: table stuff.\n\n</section>\n\n| Header 1 | Header 2 |\n| -------- | -------- |\n| Text 123 | More 456 |",
             "It adds the note markup but leaves the table untouched, to be rendered as Markdown."
         );
     }
@@ -337,8 +904,474 @@ mod tests {
         );
     }
 
+    #[test]
+    fn quote_in_note() {
+        let text = "> Note: Someone said:\n>\n> > A quote.\n>\n> And that is all.\n\nAfter.";
+        assert_eq!(
+            rewrite(text),
+            "<section class=\"note\" aria-role=\"note\">\n\nNote: Someone said:\n\n> A quote.\n\nAnd that is all.\n\n</section>\n\nAfter."
+        );
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<section class=\"note\" aria-role=\"note\">\n<p>Note: Someone said:</p>\n<blockquote>\n<p>A quote.</p>\n</blockquote>\n<p>And that is all.</p>\n</section>\n<p>After.</p>\n"
+        );
+    }
+
+    #[test]
+    fn note_in_quote() {
+        let text = "> Some quote.\n>\n> > Note: A note in it.\n> > More.\n>\n> The rest of the quote.";
+        assert_eq!(
+            rewrite(text),
+            "> Some quote.\n>\n> <section class=\"note\" aria-role=\"note\">\n>\n> Note: A note in it.\n> More.\n>\n> </section>\n>\n> The rest of the quote."
+        );
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<blockquote>\n<p>Some quote.</p>\n<section class=\"note\" aria-role=\"note\">\n<p>Note: A note in it.\nMore.</p>\n</section>\n<p>The rest of the quote.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn note_in_list_item() {
+        let text = "1. A step.\n\n   > Warning: Careful.\n   > Really.\n\n2. Another step.\n";
+        assert_eq!(
+            rewrite(text),
+            "1. A step.\n\n   <section class=\"note warning\" aria-role=\"doc-notice\">\n\n   Warning: Careful.\n   Really.\n\n   </section>\n\n2. Another step.\n"
+        );
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<ol>\n<li>\n<p>A step.</p>\n<section class=\"note warning\" aria-role=\"doc-notice\">\n<p>Warning: Careful.\nReally.</p>\n</section>\n</li>\n<li>\n<p>Another step.</p>\n</li>\n</ol>\n"
+        );
+
+        // A note right after the list marker starts on a line of its own.
+        let text = "- > Note: In a list.\n  > More.\n- Next.";
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<ul>\n<li><section class=\"note\" aria-role=\"note\">\n<p>Note: In a list.\nMore.</p>\n</section>\n</li>\n<li>\n<p>Next.</p>\n</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn only_notes_are_changed() {
+        let before =
+            "Some *emphasis* -- and \"quotes\".\r\n\n| A | B |\n|:--|--:|\n\n";
+        let after = "\n\n```rust\n> Note: not a note\n```\n\n* a   list\n";
+        let text = format!("{before}> Note: A note.\n{after}");
+        assert_eq!(
+            rewrite(&text),
+            format!("{before}<section class=\"note\" aria-role=\"note\">\n\nNote: A note.\n\n</section>\n{after}")
+        );
+
+        let text = "> A quote.\n> > Nested.\n\n- > Quoted in a list.\n";
+        assert_eq!(rewrite(text), text);
+    }
+
+    #[test]
+    fn blank_lines_around_notes() {
+        let config = Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        };
+        let text = "A paragraph.\n> Note: A note.\n# A heading";
+        assert_eq!(
+            rewrite_with(text, &config),
+            "A paragraph.\n\n---\n\n**Note:** A note.\n\n---\n\n# A heading"
+        );
+    }
+
+    #[test]
+    fn warning() {
+        let text = "> Warning: This is a warning.";
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<section class=\"note warning\" aria-role=\"doc-notice\">\n<p>Warning: This is a warning.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn tip() {
+        let text = "> Tip: This is a tip.";
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<section class=\"note tip\" aria-role=\"doc-tip\">\n<p>Tip: This is a tip.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn historical() {
+        let text = "> Historical Note: This is how it used to be.";
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<section class=\"note historical\" aria-role=\"note\">\n<p>Historical Note: This is how it used to be.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn edition() {
+        let text = "> Edition Note: This changed in Rust 2021.";
+        assert_eq!(
+            render_markdown(&rewrite(text)),
+            "<section class=\"note edition\" aria-role=\"note\">\n<p>Edition Note: This changed in Rust 2021.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn only_configured_kinds() {
+        let config = config_from(r#"{ "kinds": ["warning"] }"#).unwrap();
+        let text = "> Note: Not a note here.\n\n> Warning: Still a warning.";
+        assert_eq!(
+            render_markdown(&rewrite_with(text, &config)),
+            "<blockquote>\n<p>Note: Not a note here.</p>\n</blockquote>\n<section class=\"note warning\" aria-role=\"doc-notice\">\n<p>Warning: Still a warning.</p>\n</section>"
+        );
+
+        // Without `note`, a blockquote starting with a heading is just that.
+        let text = "> ## Header\n> Some content.";
+        assert_eq!(
+            render_markdown(&rewrite_with(text, &config)),
+            "<blockquote>\n<h2>Header</h2>\n<p>Some content.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn changed_and_new_kinds() {
+        let config = config_from(
+            r#"{
+                "kinds": ["edition", "unsafe"],
+                "kind": {
+                    "edition": { "prefix": "Rust 2024: " },
+                    "unsafe": {
+                        "prefix": "Unsafe: ",
+                        "class": "note unsafe",
+                        "role": "doc-notice"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let text = "> Rust 2024: Changed.\n\n> Unsafe: Careful.";
+        assert_eq!(
+            render_markdown(&rewrite_with(text, &config)),
+            "<section class=\"note edition\" aria-role=\"note\">\n<p>Rust 2024: Changed.</p>\n</section>\n<section class=\"note unsafe\" aria-role=\"doc-notice\">\n<p>Unsafe: Careful.</p>\n</section>"
+        );
+    }
+
+    #[test]
+    fn bad_config() {
+        let cases = [
+            (r#"{ "kinds": ["aside"] }"#, "Unknown kind of note 'aside'"),
+            (
+                r#"{ "kinds": "note" }"#,
+                "Bad config value '\"note\"' for key 'kinds'",
+            ),
+            (
+                r#"{ "kind": { "aside": { "prefix": "Aside: " } } }"#,
+                "Kind of note 'aside' needs a value for 'class'",
+            ),
+            (
+                r#"{ "kind": { "note": { "role": 1 } } }"#,
+                "Bad config value '1' for key 'kind.note.role'",
+            ),
+        ];
+
+        for (json, expected) in cases {
+            assert_eq!(config_from(json).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn reads_config_from_book() {
+        let input_json = r##"[
+            {
+                "root": "/path/to/book",
+                "config": {
+                    "book": {
+                        "authors": ["AUTHOR"],
+                        "language": "en",
+                        "multilingual": false,
+                        "src": "src",
+                        "title": "TITLE"
+                    },
+                    "preprocessor": {
+                        "trpl-note": { "kinds": ["tip"] }
+                    }
+                },
+                "renderer": "html",
+                "mdbook_version": "0.4.21"
+            },
+            {
+                "sections": [
+                    {
+                        "Chapter": {
+                            "name": "Chapter 1",
+                            "content": "> Note: A note.\n\n> Tip: A tip.\n",
+                            "number": [1],
+                            "sub_items": [],
+                            "path": "chapter_1.md",
+                            "source_path": "chapter_1.md",
+                            "parent_names": []
+                        }
+                    }
+                ],
+                "__non_exhaustive": null
+            }
+        ]"##;
+        let (ctx, book) = mdbook::preprocess::CmdPreprocessor::parse_input(
+            input_json.as_bytes(),
+        )
+        .unwrap();
+        let book = TrplNote.run(&ctx, book).unwrap();
+        let Some(mdbook::BookItem::Chapter(chapter)) = book.sections.first()
+        else {
+            panic!("expected a chapter");
+        };
+        assert_eq!(
+            render_markdown(&chapter.content),
+            "<blockquote>\n<p>Note: A note.</p>\n</blockquote>\n<section class=\"note tip\" aria-role=\"doc-tip\">\n<p>Tip: A tip.</p>\n</section>\n"
+        );
+    }
+
+    #[test]
+    fn runs_on_a_book() {
+        let fixture = trpl_preprocess::testing::Fixture::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/book"
+        ))
+        .unwrap();
+
+        let html = fixture.run(&TrplNote, "html").unwrap();
+        let chapters = trpl_preprocess::testing::chapters(&html);
+        assert_eq!(
+            chapters[0].1,
+            "# Notes\n\n<section class=\"note\" aria-role=\"note\">\n\nNote: A note.\n\n</section>\n\n<section class=\"note tip\" aria-role=\"doc-tip\">\n\nA tip.\n\n</section>\n"
+        );
+
+        let markdown = fixture.run(&TrplNote, "markdown").unwrap();
+        let chapters = trpl_preprocess::testing::chapters(&markdown);
+        assert_eq!(
+            chapters[0].1,
+            "# Notes\n\n---\n\n**Note:** A note.\n\n---\n\n---\n\n**Tip**\n\nA tip.\n\n---\n"
+        );
+    }
+
+    #[test]
+    fn github_alerts() {
+        let cases = [
+            (
+                "> [!NOTE]\n> Some text.",
+                "<section class=\"note\" aria-role=\"note\">\n<p>Some text.</p>\n</section>",
+            ),
+            (
+                "> [!WARNING]\n> Some text.\n>\n> More text.",
+                "<section class=\"note warning\" aria-role=\"doc-notice\">\n<p>Some text.</p>\n<p>More text.</p>\n</section>",
+            ),
+            (
+                "> [!Tip]\n>\n> Some text.",
+                "<section class=\"note tip\" aria-role=\"doc-tip\">\n<p>Some text.</p>\n</section>",
+            ),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(render_markdown(&rewrite(text)), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn not_github_alerts() {
+        let cases = [
+            (
+                "> [!UNKNOWN]\n> Some text.",
+                "<blockquote>\n<p>[!UNKNOWN]\nSome text.</p>\n</blockquote>\n",
+            ),
+            (
+                "> [!NOTE]* Not a title.",
+                "<blockquote>\n<p>[!NOTE]* Not a title.</p>\n</blockquote>\n",
+            ),
+            (
+                "> [!NOTE] and more on the same line.",
+                "<blockquote>\n<p>[!NOTE] and more on the same line.</p>\n</blockquote>\n",
+            ),
+            (
+                "> [a bracket] and a [link](https://example.com).",
+                "<blockquote>\n<p>[a bracket] and a <a href=\"https://example.com\">link</a>.</p>\n</blockquote>\n",
+            ),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(render_markdown(&rewrite(text)), expected, "{text}");
+        }
+
+        // Only kinds which are turned on count.
+        let config = config_from(r#"{ "kinds": ["note"] }"#).unwrap();
+        assert_eq!(
+            render_markdown(&rewrite_with("> [!TIP]\n> Some text.", &config)),
+            "<blockquote>\n<p>[!TIP]\nSome text.</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn collapsible_notes() {
+        let cases = [
+            (
+                "> [!NOTE]- How `cargo` builds it\n> Some text.",
+                "<details class=\"note\" aria-role=\"note\">\n<summary>How <code>cargo</code> builds it</summary>\n<p>Some text.</p>\n</details>",
+            ),
+            (
+                "> [!WARNING]+\n>\n> Some text.",
+                "<details class=\"note warning\" aria-role=\"doc-notice\" open>\n<summary>Warning</summary>\n<p>Some text.</p>\n</details>",
+            ),
+            (
+                "> ## Editions {.collapsed}\n>\n> Some text.",
+                "<details class=\"note\" aria-role=\"note\">\n<summary>Editions</summary>\n<p>Some text.</p>\n</details>",
+            ),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(render_markdown(&rewrite(text)), expected, "{text}");
+        }
+
+        assert_eq!(
+            rewrite("> [!TIP]- A title\n> Some text."),
+            "<details class=\"note tip\" aria-role=\"doc-tip\">\n<summary>A title</summary>\n\nSome text.\n\n</details>"
+        );
+    }
+
+    #[test]
+    fn collapsible_notes_in_other_modes() {
+        let simple = Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        };
+        let print = Config {
+            mode: Mode::Print,
+            ..Config::default()
+        };
+        let cases = [
+            (
+                &simple,
+                "> [!NOTE]- A title\n> Some text.",
+                "---\n\n**A title**\n\nSome text.\n\n---",
+            ),
+            (
+                &simple,
+                "> [!NOTE]-\n> Some text.",
+                "---\n\n**Note**\n\nSome text.\n\n---",
+            ),
+            (
+                &simple,
+                "> ## Editions {.collapsed}\n> Some text.",
+                "---\n\n## Editions {.collapsed}\nSome text.\n\n---",
+            ),
+            (
+                &print,
+                "> [!TIP]+ A title\n> Some text.",
+                "::: {.note .tip}\n\n**A title**\n\nSome text.\n\n:::",
+            ),
+        ];
+
+        for (config, text, expected) in cases {
+            assert_eq!(rewrite_with(text, config), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn simple_mode() {
+        let config = Config {
+            mode: Mode::Simple,
+            ..Config::default()
+        };
+        let cases = [
+            (
+                "Before.\n\n> Note: This is some text.\n> It keeps going.\n\nAfter.",
+                "Before.\n\n---\n\n**Note:** This is some text.\nIt keeps going.\n\n---\n\nAfter.",
+            ),
+            (
+                "> Historical Note: Old.",
+                "---\n\n**Historical Note:** Old.\n\n---",
+            ),
+            (
+                "> ## Header\n> Some content.",
+                "---\n\n## Header\nSome content.\n\n---",
+            ),
+            (
+                "> [!WARNING]\n> Some text.",
+                "---\n\n**Warning**\n\nSome text.\n\n---",
+            ),
+            (
+                "> [!TIP]\n>\n> Some text.",
+                "---\n\n**Tip**\n\nSome text.\n\n---",
+            ),
+        ];
+
+        for (text, expected) in cases {
+            let processed = rewrite_with(text, &config);
+            assert_eq!(processed, expected, "{text}");
+            assert!(!render_markdown(&processed).contains("<section"));
+        }
+    }
+
+    #[test]
+    fn print_mode() {
+        let config = Config {
+            mode: Mode::Print,
+            ..Config::default()
+        };
+        let processed =
+            rewrite_with("> Warning: Some text.\n> More text.", &config);
+        assert_eq!(
+            processed.trim_start(),
+            "::: {.note .warning}\n\nWarning: Some text.\nMore text.\n\n:::"
+        );
+    }
+
+    #[test]
+    fn output_mode_config() {
+        let cases = [
+            (r#"{}"#, "html", Mode::Default),
+            (r#"{ "output-mode": "simple" }"#, "html", Mode::Simple),
+            (r#"{ "output-mode": "print" }"#, "markdown", Mode::Print),
+            (
+                r#"{ "output-mode": { "html": "default", "markdown": "simple" } }"#,
+                "markdown",
+                Mode::Simple,
+            ),
+            (
+                r#"{ "output-mode": { "html": "default", "markdown": "simple" } }"#,
+                "html",
+                Mode::Default,
+            ),
+        ];
+        for (json, renderer, expected) in cases {
+            let table: toml::Value = serde_json::from_str(json).unwrap();
+            let config = Config::from_table(ConfigTable::new(
+                table.as_table().unwrap(),
+                renderer,
+            ))
+            .unwrap();
+            assert_eq!(config.mode, expected, "{json} {renderer}");
+        }
+
+        let cases = [
+            (
+                r#"{ "output-mode": "fancy" }"#,
+                "Bad config value '\"fancy\"' for key 'output-mode'",
+            ),
+            (
+                r#"{ "output-mode": { "epub": "fancy" } }"#,
+                "Bad config value '\"fancy\"' for key 'output-mode.epub'",
+            ),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(config_from(json).unwrap_err().to_string(), expected);
+        }
+    }
+
+    fn config_from(json: &str) -> std::result::Result<Config, Error> {
+        let table: toml::Value = serde_json::from_str(json).unwrap();
+        Config::from_table(ConfigTable::new(table.as_table().unwrap(), "html"))
+    }
+
     fn render_markdown(text: &str) -> String {
-        let parser = new_cmark_parser(text, true);
+        let parser = mdbook::utils::new_cmark_parser(text, true);
         let mut buf = String::new();
         pulldown_cmark::html::push_html(&mut buf, parser);
         buf
@@ -1,25 +1,23 @@
-use std::io;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use clap::{self, Parser, Subcommand};
-use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
+use trpl_preprocess::TrplPreprocessor;
 
-use mdbook_trpl_note::TrplNote;
+use mdbook_trpl_note::{
+    check::{check, Finding},
+    Config, TrplNote,
+};
 
 fn main() -> Result<(), String> {
     let cli = Cli::parse();
-    let simple_note = TrplNote;
-    if let Some(Command::Supports { renderer }) = cli.command {
-        return if simple_note.supports_renderer(&renderer) {
-            Ok(())
-        } else {
-            Err(format!("Renderer '{renderer}' is unsupported"))
-        };
+    match cli.command {
+        Some(Command::Supports { renderer }) => TrplNote.supports(&renderer),
+        Some(Command::Check { book, paths }) => check_chapters(&book, &paths),
+        None => TrplNote.preprocess(),
     }
-
-    let (ctx, book) =
-        CmdPreprocessor::parse_input(io::stdin()).map_err(|e| format!("blah: {e}"))?;
-    let processed = simple_note.run(&ctx, book).map_err(|e| format!("{e}"))?;
-    serde_json::to_writer(io::stdout(), &processed).map_err(|e| format!("{e}"))
 }
 
 /// A simple preprocessor for semantic notes in _The Rust Programming Language_.
@@ -35,4 +33,84 @@ enum Command {
     ///
     /// All renderers are supported! This is the contract for mdBook.
     Supports { renderer: String },
+
+    /// List note-like markup in chapters which will not become notes, like
+    /// `> **Note:**` or a paragraph starting with `Note: `.
+    ///
+    /// Exits with an error if there is any.
+    Check {
+        /// The book the chapters are in, whose `book.toml` configures the
+        /// kinds of note, if it has one.
+        #[arg(long, default_value = ".")]
+        book: PathBuf,
+
+        /// The chapters to check, or directories to check all the Markdown
+        /// files in.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+fn check_chapters(book: &Path, paths: &[PathBuf]) -> Result<(), String> {
+    let mut chapters = vec![];
+    for path in paths {
+        markdown_files(path, &mut chapters)?;
+    }
+
+    let config = book_config(book)?;
+    let mut count = 0;
+    for chapter in chapters {
+        let display = chapter.display();
+        let src = fs::read_to_string(&chapter)
+            .map_err(|e| format!("Could not read {display}: {e}"))?;
+        for Finding { line, message } in check(&src, &config) {
+            println!("{display}:{line}: {message}");
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        Ok(())
+    } else {
+        Err(format!("{count} note(s) which will not be rewritten"))
+    }
+}
+
+/// The config in the `book.toml` in `book`, or the default one if there is no
+/// `book.toml`. The kinds of note are the same for every renderer, so which
+/// one it is read for only matters for checking `output-mode`.
+fn book_config(book: &Path) -> Result<Config, String> {
+    let path = book.join("book.toml");
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let book = mdbook::Config::from_disk(&path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    Config::from_book(&book, "html")
+        .map_err(|e| format!("Bad config in {}: {e}", path.display()))
+}
+
+/// `path` if it is a file, or every Markdown file under it if it is a
+/// directory, in order.
+fn markdown_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "md") {
+            markdown_files(&entry, files)?;
+        }
+    }
+    Ok(())
 }
@@ -1,4 +1,5 @@
 use assert_cmd::Command;
+use trpl_preprocess::testing::Fixture;
 
 #[test]
 fn supports_html_renderer() {
@@ -18,5 +19,57 @@ fn errors_for_other_renderers() {
     assert!(cmd.is_err());
 }
 
-// It would be nice to add an actual fixture for an mdbook, but doing *that* is
-// going to be a bit of a pain, and what I have should cover it for now.
+#[test]
+fn check_passes_for_notes() {
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["check", "tests/fixtures/check/clean.md"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn check_fails_for_unconverted_notes() {
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["check", "tests/fixtures/check"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tests/fixtures/check/unconverted.md:3: blockquote starting with `Note:` in bold or emphasis is not a note; start it with plain `Note:` instead\n\
+         tests/fixtures/check/unconverted.md:5: paragraph starting with `Note:` is not a note; put it in a blockquote\n"
+    );
+}
+
+#[test]
+fn check_uses_the_books_kinds() {
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            "check",
+            "--book",
+            "tests/fixtures/check-kinds",
+            "tests/fixtures/check-kinds/src",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tests/fixtures/check-kinds/src/chapter.md:7: blockquote starting with `Unsafe:` in bold or emphasis is not a note; start it with plain `Unsafe:` instead\n\
+         tests/fixtures/check-kinds/src/chapter.md:9: paragraph starting with `Unsafe:` is not a note; put it in a blockquote\n"
+    );
+}
+
+/// Run the binary on the fixture book, through the same JSON `mdbook build`
+/// sends it, and compare what it sends back with the snapshots in
+/// `tests/fixtures/book/snapshots`. Run with `BLESS=1` to update them.
+#[test]
+fn preprocesses_a_book() {
+    Fixture::load("tests/fixtures/book").unwrap().assert_snapshots(
+        env!("CARGO_BIN_EXE_mdbook-trpl-note"),
+        &["html", "markdown"],
+    );
+}
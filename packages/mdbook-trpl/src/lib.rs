@@ -0,0 +1,192 @@
+use mdbook::{
+    book::Book,
+    errors::Result,
+    preprocess::{Preprocessor, PreprocessorContext},
+};
+use mdbook_trpl_listing::TrplListing;
+use mdbook_trpl_note::TrplNote;
+use trpl_preprocess::{
+    config::ConfigTable,
+    stage::{self, Stage},
+    TrplPreprocessor,
+};
+
+/// The stages, in the order they run, with the names of their tables in
+/// `[preprocessor.trpl]`.
+pub const STAGES: &[(&str, &dyn Stage)] =
+    &[("listing", &TrplListing), ("note", &TrplNote)];
+
+/// The book's preprocessors as one: each of [`STAGES`] does what its own
+/// preprocessor would, to the chapters as the stages before it left them, but
+/// the book is only read and written once, and a chapter is only parsed again
+/// for a stage when one before it changed the chapter.
+///
+/// Each stage is configured with a table of its own in `book.toml`, which takes
+/// the same options as its preprocessor's:
+///
+/// ```toml
+/// [preprocessor.trpl.listing]
+/// output-mode = { html = "default", markdown = "simple" }
+/// permalinks = true
+///
+/// [preprocessor.trpl.note]
+/// output-mode = { html = "default", markdown = "simple" }
+/// ```
+///
+/// Every stage runs, with its default configuration if it has no table, unless
+/// its table has `enable = false`.
+pub struct Trpl;
+
+impl Preprocessor for Trpl {
+    fn name(&self) -> &str {
+        "trpl"
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
+        let table = ConfigTable::from_context(ctx, self.name());
+        let mut stages = vec![];
+        for &(name, stage) in STAGES {
+            let config = match table {
+                Some(table) => table.table(name)?,
+                None => None,
+            };
+            let enabled = match config {
+                Some(config) => config.bool("enable")?.unwrap_or(true),
+                None => true,
+            };
+            if enabled {
+                stages.push((name, stage, config));
+            }
+        }
+
+        stage::run(ctx, book, &stages)
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        trpl_preprocess::supports_renderer(renderer)
+    }
+}
+
+impl TrplPreprocessor for Trpl {
+    fn report(&self, error: mdbook::errors::Error) -> String {
+        STAGES
+            .iter()
+            .find_map(|(_, stage)| stage.report(&error))
+            .unwrap_or_else(|| format!("{error}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::book::{Chapter, SectionNumber};
+    use trpl_preprocess::testing::{chapters, Fixture};
+
+    use super::*;
+
+    const BOOK: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/book");
+
+    #[test]
+    fn runs_every_stage() {
+        let fixture = Fixture::load(BOOK).unwrap();
+
+        let html = fixture.run(&Trpl, "html").unwrap();
+        assert_eq!(
+            chapters(&html)[0].1,
+            r#"# Getting Started
+
+<figure class="listing" id="listing-1-1">
+<span class="file-name">Filename: src/main.rs</span>
+
+```rust
+fn main() {}
+```
+
+<figcaption>Listing 1-1: Hello, <em>world</em></figcaption>
+</figure>
+
+<section class="note" aria-role="note">
+
+Note: The listing and this note are rewritten together.
+
+</section>
+"#
+        );
+
+        let markdown = fixture.run(&Trpl, "markdown").unwrap();
+        assert_eq!(
+            chapters(&markdown)[0].1,
+            r#"# Getting Started
+
+Filename: src/main.rs
+
+```rust
+fn main() {}
+```
+
+Listing 1-1: Hello, *world*
+
+---
+
+**Note:** The listing and this note are rewritten together.
+
+---
+"#
+        );
+    }
+
+    #[test]
+    fn disabled_stage() {
+        let (mut ctx, book) = Fixture::load(BOOK).unwrap().parse("html");
+        ctx.config
+            .set("preprocessor.trpl.note.enable", false)
+            .unwrap();
+
+        let book = Trpl.run(&ctx, book).unwrap();
+        let (_, content) = &chapters(&book)[0];
+        assert!(content.contains("<figure"), "{content}");
+        assert!(
+            content.ends_with(
+                "\n> Note: The listing and this note are rewritten together.\n"
+            ),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn bad_stage_config() {
+        let (mut ctx, book) = Fixture::load(BOOK).unwrap().parse("html");
+        ctx.config.set("preprocessor.trpl.listing", "on").unwrap();
+
+        let error = Trpl.run(&ctx, book).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bad config value '\"on\"' for key 'listing'"
+        );
+    }
+
+    #[test]
+    fn listing_markup_in_a_note() {
+        let (ctx, _) = Fixture::load(BOOK).unwrap().parse("html");
+        let mut chapter = Chapter::new(
+            "Nested",
+            String::from(
+                "<Listing id=\"x\">\n\n```rust\nfn main() {}\n```\n\n</Listing>\n\n> Note: As shown in <ListingRef id=\"x\" />, it works.\n",
+            ),
+            "nested.md",
+            vec![],
+        );
+        chapter.number = Some(SectionNumber(vec![1]));
+        let mut book = Book::new();
+        book.push_item(chapter);
+
+        let book = Trpl.run(&ctx, book).unwrap();
+        assert!(
+            chapters(&book)[0].1.ends_with(
+                "<section class=\"note\" aria-role=\"note\">\n\nNote: As shown in <a href=\"#listing-1-1\">Listing 1-1</a>, it works.\n\n</section>\n"
+            ),
+            "{}",
+            chapters(&book)[0].1
+        );
+    }
+}
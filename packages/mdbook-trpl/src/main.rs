@@ -0,0 +1,28 @@
+use clap::{self, Parser, Subcommand};
+use trpl_preprocess::TrplPreprocessor;
+
+use mdbook_trpl::Trpl;
+
+fn main() -> Result<(), String> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Supports { renderer }) => Trpl.supports(&renderer),
+        None => Trpl.preprocess(),
+    }
+}
+
+/// One preprocessor for everything _The Rust Programming Language_ needs,
+/// running each of its rewrites as a stage.
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Is the renderer supported?
+    ///
+    /// All renderers are supported! This is the contract for mdBook.
+    Supports { renderer: String },
+}
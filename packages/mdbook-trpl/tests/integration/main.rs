@@ -0,0 +1,31 @@
+use assert_cmd::Command;
+use trpl_preprocess::testing::Fixture;
+
+#[test]
+fn supports_html_renderer() {
+    let cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["supports", "html"])
+        .ok();
+    assert!(cmd.is_ok());
+}
+
+#[test]
+fn errors_for_other_renderers() {
+    let cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["supports", "total-nonsense"])
+        .ok();
+    assert!(cmd.is_err());
+}
+
+/// Run the binary on the fixture book, through the same JSON `mdbook build`
+/// sends it, and compare what it sends back with the snapshots in
+/// `tests/fixtures/book/snapshots`. Run with `BLESS=1` to update them.
+#[test]
+fn preprocesses_a_book() {
+    Fixture::load("tests/fixtures/book").unwrap().assert_snapshots(
+        env!("CARGO_BIN_EXE_mdbook-trpl"),
+        &["html", "markdown"],
+    );
+}
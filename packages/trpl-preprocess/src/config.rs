@@ -0,0 +1,210 @@
+//! Reading a preprocessor's `[preprocessor.<name>]` table from `book.toml`.
+
+use mdbook::preprocess::PreprocessorContext;
+use toml::{map::Map, Value};
+
+/// What to turn the book's markup into, from the `output-mode` configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// HTML, for mdBook's HTML renderer.
+    Default,
+    /// Plain Markdown, for renderers which do not keep HTML.
+    Simple,
+    /// Markdown with Pandoc's markers for the semantics the print version of
+    /// the book needs.
+    Print,
+}
+
+/// Trivial marker struct to indicate an internal error.
+///
+/// The caller has enough info to do what it needs without passing data around.
+#[derive(Debug)]
+pub struct ParseErr;
+
+impl TryFrom<&str> for Mode {
+    type Error = ParseErr;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "default" => Ok(Mode::Default),
+            "simple" => Ok(Mode::Simple),
+            "print" => Ok(Mode::Print),
+            _ => Err(ParseErr),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Bad config value '{value}' for key '{key}'")]
+    BadValue { key: String, value: String },
+}
+
+impl Error {
+    pub fn bad_value(key: impl Into<String>, value: &Value) -> Error {
+        Error::BadValue {
+            key: key.into(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// A preprocessor's table from `book.toml`, read for the renderer it is
+/// running for.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigTable<'a> {
+    table: &'a Map<String, Value>,
+    renderer: &'a str,
+}
+
+impl<'a> ConfigTable<'a> {
+    pub fn new(table: &'a Map<String, Value>, renderer: &'a str) -> Self {
+        ConfigTable { table, renderer }
+    }
+
+    /// The `[preprocessor.<name>]` table for the book in `ctx`, if it has one.
+    pub fn from_context(
+        ctx: &'a PreprocessorContext,
+        name: &str,
+    ) -> Option<ConfigTable<'a>> {
+        ctx.config
+            .get_preprocessor(name)
+            .map(|table| ConfigTable::new(table, &ctx.renderer))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a Value> {
+        self.table.get(key)
+    }
+
+    /// `output-mode`, which is either a mode for every renderer, or a table of
+    /// renderer names to modes. Every entry in the table is checked, not just
+    /// the one for the current renderer, so that mistakes show up whichever
+    /// renderer happens to be running.
+    pub fn mode(&self) -> Result<Mode, Error> {
+        let key = "output-mode";
+        let Some(value) = self.get(key) else {
+            return Ok(Mode::Default);
+        };
+
+        match (value.as_str(), value.as_table()) {
+            (Some(s), _) => {
+                Mode::try_from(s).map_err(|_| Error::bad_value(key, value))
+            }
+
+            (None, Some(modes)) => {
+                let mut mode = Mode::Default;
+                for (renderer, value) in modes {
+                    let renderer_mode = value
+                        .as_str()
+                        .and_then(|s| Mode::try_from(s).ok())
+                        .ok_or_else(|| {
+                            Error::bad_value(format!("{key}.{renderer}"), value)
+                        })?;
+                    if renderer == self.renderer {
+                        mode = renderer_mode;
+                    }
+                }
+                Ok(mode)
+            }
+
+            (None, None) => Err(Error::bad_value(key, value)),
+        }
+    }
+
+    /// The table at `key`, if it is there, for the same renderer, like a
+    /// stage's table in its preprocessor's.
+    pub fn table(&self, key: &str) -> Result<Option<ConfigTable<'a>>, Error> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .as_table()
+                    .map(|table| ConfigTable::new(table, self.renderer))
+                    .ok_or_else(|| Error::bad_value(key, value))
+            })
+            .transpose()
+    }
+
+    /// The value of `key`, if it is there, which must be a boolean.
+    pub fn bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        self.get(key)
+            .map(|value| {
+                value.as_bool().ok_or_else(|| Error::bad_value(key, value))
+            })
+            .transpose()
+    }
+
+    /// The value of `key`, if it is there, which must be a string.
+    pub fn str(&self, key: &str) -> Result<Option<&'a str>, Error> {
+        self.get(key)
+            .map(|value| {
+                value.as_str().ok_or_else(|| Error::bad_value(key, value))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(json: &str) -> Map<String, Value> {
+        let value: Value = serde_json::from_str(json).unwrap();
+        value.as_table().unwrap().clone()
+    }
+
+    #[test]
+    fn output_mode() {
+        let cases = [
+            (r#"{}"#, "html", Mode::Default),
+            (r#"{ "output-mode": "simple" }"#, "html", Mode::Simple),
+            (r#"{ "output-mode": "print" }"#, "markdown", Mode::Print),
+            (
+                r#"{ "output-mode": { "html": "default", "markdown": "simple" } }"#,
+                "markdown",
+                Mode::Simple,
+            ),
+            (
+                r#"{ "output-mode": { "markdown": "simple" } }"#,
+                "html",
+                Mode::Default,
+            ),
+        ];
+        for (json, renderer, expected) in cases {
+            let table = table(json);
+            let mode = ConfigTable::new(&table, renderer).mode().unwrap();
+            assert_eq!(mode, expected, "{json} {renderer}");
+        }
+    }
+
+    #[test]
+    fn bad_values() {
+        let table = table(
+            r#"{
+                "output-mode": { "html": "default", "epub": "fancy" },
+                "flag": "yes",
+                "path": 1,
+                "stage": { "flag": true }
+            }"#,
+        );
+        let config = ConfigTable::new(&table, "html");
+        assert_eq!(
+            config.mode().unwrap_err().to_string(),
+            "Bad config value '\"fancy\"' for key 'output-mode.epub'"
+        );
+        assert_eq!(
+            config.bool("flag").unwrap_err().to_string(),
+            "Bad config value '\"yes\"' for key 'flag'"
+        );
+        assert_eq!(
+            config.str("path").unwrap_err().to_string(),
+            "Bad config value '1' for key 'path'"
+        );
+        assert_eq!(
+            config.table("flag").unwrap_err().to_string(),
+            "Bad config value '\"yes\"' for key 'flag'"
+        );
+        let stage = config.table("stage").unwrap().unwrap();
+        assert_eq!(stage.bool("flag").unwrap(), Some(true));
+        assert_eq!(config.bool("missing").unwrap(), None);
+    }
+}
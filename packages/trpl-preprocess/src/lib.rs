@@ -0,0 +1,69 @@
+//! What the preprocessors for _The Rust Programming Language_ have in common:
+//! how they run as mdBook preprocessors, how they read their configuration,
+//! and how they rewrite a chapter's source without disturbing the rest of it.
+//!
+//! This is a path dependency of `mdbook-trpl-listing` and `mdbook-trpl-note`
+//! rather than a published crate, so that they can still be built the same
+//! way from `rust-lang/rust`.
+
+use std::io::{self, Read, Write};
+
+use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
+
+pub mod config;
+pub mod rewrite;
+pub mod stage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use config::Mode;
+
+/// The renderers the book's preprocessors support: mdBook's own HTML and
+/// Markdown renderers, and `test`, for `mdbook test`.
+pub const RENDERERS: &[&str] = &["html", "markdown", "test"];
+
+/// Whether the book's preprocessors support `renderer`. Every one of them
+/// supports the same ones, so this is what their
+/// [`Preprocessor::supports_renderer`] should be.
+pub fn supports_renderer(renderer: &str) -> bool {
+    RENDERERS.contains(&renderer)
+}
+
+/// An mdBook [`Preprocessor`] for the book, which its binary can run with the
+/// methods here instead of handling mdBook's protocol itself.
+pub trait TrplPreprocessor: Preprocessor {
+    /// The message to exit with when running fails. mdBook only shows the
+    /// preprocessor's exit status and stderr, so anything which does not fit
+    /// in one message should be printed to stderr here.
+    fn report(&self, error: mdbook::errors::Error) -> String {
+        format!("{error}")
+    }
+
+    /// Answer mdBook's `supports <renderer>` check.
+    fn supports(&self, renderer: &str) -> Result<(), String> {
+        if self.supports_renderer(renderer) {
+            Ok(())
+        } else {
+            Err(format!("Renderer '{renderer}' is unsupported"))
+        }
+    }
+
+    /// Preprocess the book mdBook sends on stdin, and send it back on stdout.
+    fn preprocess(&self) -> Result<(), String> {
+        self.preprocess_json(io::stdin(), io::stdout())
+    }
+
+    /// Preprocess the book in mdBook's JSON from `input`, and write it to
+    /// `output` the same way.
+    fn preprocess_json(
+        &self,
+        input: impl Read,
+        output: impl Write,
+    ) -> Result<(), String> {
+        let (ctx, book) =
+            CmdPreprocessor::parse_input(input).map_err(|e| format!("{e}"))?;
+        let processed =
+            self.run(&ctx, book).map_err(|error| self.report(error))?;
+        serde_json::to_writer(output, &processed).map_err(|e| format!("{e}"))
+    }
+}
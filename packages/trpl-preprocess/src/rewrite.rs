@@ -0,0 +1,119 @@
+//! Rewriting a chapter in place. The preprocessors find what to change from
+//! the spans pulldown-cmark gives each event, and replace only those spans of
+//! the source, so that everything else is left exactly as it was for mdBook's
+//! own Markdown handling, instead of going through a round trip which would
+//! normalize it.
+
+use std::ops::Range;
+
+use mdbook::utils::new_cmark_parser;
+use pulldown_cmark::{html, Event, TagEnd};
+
+/// Every event in `src`, with its span, parsed the same way mdBook parses
+/// chapters.
+pub fn events(src: &str) -> Vec<(Event<'_>, Range<usize>)> {
+    new_cmark_parser(src, true).into_offset_iter().collect()
+}
+
+/// Text to put in place of a span of a chapter's source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+/// Apply `edits`, which must be in order and not overlap, to `src`. Every byte
+/// outside of their spans is kept exactly as it was.
+pub fn splice(src: &str, edits: impl IntoIterator<Item = Edit>) -> String {
+    let mut buf = String::with_capacity(src.len() * 2);
+    let mut copied = 0;
+    for Edit { span, text } in edits {
+        debug_assert!(copied <= span.start, "edits out of order at {span:?}");
+        buf.push_str(&src[copied..span.start]);
+        buf.push_str(&text);
+        copied = span.end;
+    }
+    buf.push_str(&src[copied..]);
+    buf
+}
+
+/// `range` in `src` without any whitespace at its end, such as the line
+/// ending after an HTML block, a fenced code block, or a blockquote.
+pub fn trim_end(src: &str, range: Range<usize>) -> Range<usize> {
+    let len = src[range.clone()].trim_end().len();
+    range.start..range.start + len
+}
+
+/// Render Markdown to HTML which can go anywhere inline HTML can, such as in a
+/// `<figcaption>` or a `<summary>`.
+///
+/// Block-level structure is dropped and only its contents are kept: the
+/// paragraph every caption gets wrapped in, but also the heading or list a
+/// caption like `# of items` or `1. Setup` would otherwise turn into.
+pub fn inline_html(markdown: &str) -> String {
+    let events =
+        new_cmark_parser(markdown, true).filter_map(|event| match event {
+            Event::Start(tag) if !is_inline(&TagEnd::from(tag.clone())) => None,
+            Event::End(tag) if !is_inline(&tag) => None,
+            Event::SoftBreak => Some(Event::Text(" ".into())),
+            event => Some(event),
+        });
+
+    let mut buf = String::with_capacity(markdown.len() * 2);
+    html::push_html(&mut buf, events);
+    buf
+}
+
+fn is_inline(tag: &TagEnd) -> bool {
+    matches!(
+        tag,
+        TagEnd::Emphasis
+            | TagEnd::Strong
+            | TagEnd::Strikethrough
+            | TagEnd::Link
+            | TagEnd::Image
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splices_only_the_edits() {
+        let src = "| A | B |\n|:--|--:|\n\n<Tag>\n\n\"Quotes\" -- stay\r\n";
+        let start = src.find("<Tag>").unwrap();
+        let edits = [Edit {
+            span: trim_end(src, start..start + "<Tag>\n\n".len()),
+            text: String::from("<figure>"),
+        }];
+        assert_eq!(
+            splice(src, edits),
+            "| A | B |\n|:--|--:|\n\n<figure>\n\n\"Quotes\" -- stay\r\n"
+        );
+        assert_eq!(splice(src, []), src);
+    }
+
+    #[test]
+    fn events_have_spans() {
+        let src = "Some *text*.\n\n> A quote.\n";
+        let spans: Vec<&str> = events(src)
+            .into_iter()
+            .filter(|(event, _)| matches!(event, Event::Start(_)))
+            .map(|(_, span)| &src[trim_end(src, span)])
+            .collect();
+        assert_eq!(
+            spans,
+            vec!["Some *text*.", "*text*", "> A quote.", "A quote."]
+        );
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(
+            inline_html("A *caption* with `code`\nacross lines"),
+            "A <em>caption</em> with <code>code</code> across lines"
+        );
+        assert_eq!(inline_html("# of items"), "of items");
+    }
+}
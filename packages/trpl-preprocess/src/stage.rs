@@ -0,0 +1,254 @@
+//! Running rewrites as stages of one preprocessor. Each stage sees the
+//! chapters as the stages before it left them, just as if they were separate
+//! preprocessors, but the book is only read and written once, and a chapter is
+//! only parsed again for a stage if one before it changed the chapter.
+
+use std::ops::Range;
+
+use mdbook::{
+    book::{Book, Chapter},
+    errors::{Error, Result},
+    preprocess::PreprocessorContext,
+    BookItem,
+};
+use pulldown_cmark::Event;
+
+use crate::{
+    config::ConfigTable,
+    rewrite::{events, splice, Edit},
+};
+
+/// A chapter, along with every event in its content as the stages before the
+/// current one left it.
+pub struct Parsed<'a> {
+    pub chapter: &'a Chapter,
+    pub events: Vec<(Event<'a>, Range<usize>)>,
+    src: &'a str,
+}
+
+impl<'a> Parsed<'a> {
+    pub fn new(chapter: &'a Chapter) -> Parsed<'a> {
+        Parsed::with_src(chapter, &chapter.content)
+    }
+
+    /// `chapter`, with `src` in place of its content.
+    pub fn with_src(chapter: &'a Chapter, src: &'a str) -> Parsed<'a> {
+        Parsed {
+            chapter,
+            events: events(src),
+            src,
+        }
+    }
+
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    /// Whether `src` is something other than the chapter's own content.
+    fn is_rewritten(&self) -> bool {
+        !std::ptr::eq(self.src, self.chapter.content.as_str())
+    }
+}
+
+/// Every chapter in `book`, parsed, in book order.
+pub fn parse(book: &Book) -> Vec<Parsed<'_>> {
+    book.iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => Some(Parsed::new(chapter)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What a stage does to a book.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// The edits to each chapter, in the same order as the chapters the stage
+    /// was given. Each chapter's edits must be in order and not overlap.
+    pub edits: Vec<Vec<Edit>>,
+    /// Chapters to add to the end of the book.
+    pub chapters: Vec<Chapter>,
+}
+
+/// One of the rewrites the book's preprocessors do.
+pub trait Stage {
+    /// Work out the changes to the book from its `chapters`, in book order.
+    /// `config` is the stage's table, if the book has one.
+    fn rewrite(
+        &self,
+        ctx: &PreprocessorContext,
+        config: Option<ConfigTable<'_>>,
+        chapters: &[Parsed<'_>],
+    ) -> Result<Changes>;
+
+    /// The message to exit with for `error`, if it came from this stage and
+    /// needs more than its `Display`; see [`crate::TrplPreprocessor::report`].
+    fn report(&self, _error: &Error) -> Option<String> {
+        None
+    }
+}
+
+/// Run each of `stages`, which is a name for the stage, the stage, and its
+/// config, on `book`, in order.
+pub fn run(
+    ctx: &PreprocessorContext,
+    mut book: Book,
+    stages: &[(&str, &dyn Stage, Option<ConfigTable<'_>>)],
+) -> Result<Book> {
+    let (contents, added) = run_from(ctx, stages, parse(&book))?;
+
+    let mut contents = contents.into_iter();
+    apply(&mut book.sections, &mut contents);
+    for mut chapter in added {
+        if let Some(Some(content)) = contents.next() {
+            chapter.content = content;
+        }
+        book.push_item(chapter);
+    }
+
+    Ok(book)
+}
+
+/// Run the first of `stages` on `chapters`, and the rest on the chapters as
+/// it leaves them, along with any it adds.
+///
+/// Each call keeps the chapters the stage changes alive for the rest of the
+/// stages, so that the events of the ones it does not change can be used again
+/// instead of parsing them again. The result is the final content of every
+/// chapter any stage changed, in order, followed by the chapters the stages
+/// added.
+fn run_from(
+    ctx: &PreprocessorContext,
+    stages: &[(&str, &dyn Stage, Option<ConfigTable<'_>>)],
+    chapters: Vec<Parsed<'_>>,
+) -> Result<(Vec<Option<String>>, Vec<Chapter>)> {
+    let Some((&(_, stage, config), rest)) = stages.split_first() else {
+        let contents = chapters
+            .iter()
+            .map(|parsed| {
+                parsed.is_rewritten().then(|| parsed.src().to_string())
+            })
+            .collect();
+        return Ok((contents, vec![]));
+    };
+
+    let Changes {
+        edits,
+        chapters: mut added,
+    } = stage.rewrite(ctx, config, &chapters)?;
+    let rewritten: Vec<Option<String>> = chapters
+        .iter()
+        .zip(edits.into_iter().chain(std::iter::repeat_with(Vec::new)))
+        .map(|(parsed, edits)| {
+            (!edits.is_empty()).then(|| splice(parsed.src(), edits))
+        })
+        .collect();
+
+    let mut next: Vec<Parsed> = chapters
+        .into_iter()
+        .zip(&rewritten)
+        .map(|(parsed, rewritten)| match rewritten {
+            Some(src) => Parsed::with_src(parsed.chapter, src),
+            None => parsed,
+        })
+        .collect();
+    next.extend(added.iter().map(Parsed::new));
+
+    let (contents, more) = run_from(ctx, rest, next)?;
+    added.extend(more);
+    Ok((contents, added))
+}
+
+/// Put each chapter's new content, if it has one, in place, in the same order
+/// as `Book::iter`, which is not the order `Book::for_each_mut` goes in: that
+/// visits a chapter's sections before the chapter itself.
+fn apply(
+    items: &mut [BookItem],
+    contents: &mut impl Iterator<Item = Option<String>>,
+) {
+    for item in items {
+        if let BookItem::Chapter(chapter) = item {
+            if let Some(Some(content)) = contents.next() {
+                chapter.content = content;
+            }
+            apply(&mut chapter.sub_items, contents);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{chapters, Fixture};
+
+    const BOOK: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/book");
+
+    /// Replaces the first occurrence of some text in each paragraph, and adds a
+    /// chapter with the number of chapters it was in and the replacement.
+    struct Replace(&'static str, &'static str);
+
+    impl Stage for Replace {
+        fn rewrite(
+            &self,
+            _: &PreprocessorContext,
+            _: Option<ConfigTable<'_>>,
+            chapters: &[Parsed<'_>],
+        ) -> Result<Changes> {
+            let mut changes = Changes::default();
+            let mut count = 0;
+            for chapter in chapters {
+                let mut edits = vec![];
+                for (event, range) in &chapter.events {
+                    if let Event::Text(text) = event {
+                        if let Some(start) = text.find(self.0) {
+                            let start = range.start + start;
+                            edits.push(Edit {
+                                span: start..start + self.0.len(),
+                                text: self.1.to_string(),
+                            });
+                        }
+                    }
+                }
+                count += usize::from(!edits.is_empty());
+                changes.edits.push(edits);
+            }
+            changes.chapters.push(Chapter::new(
+                self.1,
+                format!("{count} {}\n", self.1),
+                format!("{}.md", self.1),
+                vec![],
+            ));
+            Ok(changes)
+        }
+    }
+
+    #[test]
+    fn later_stages_see_earlier_changes() {
+        let (ctx, book) = Fixture::load(BOOK).unwrap().parse("html");
+
+        let book = run(
+            &ctx,
+            book,
+            &[
+                ("some", &Replace("Some text", "Some words"), None),
+                ("words", &Replace("words", "terms"), None),
+            ],
+        )
+        .unwrap();
+
+        let contents: Vec<String> = chapters(&book)
+            .into_iter()
+            .map(|(_, content)| content)
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                "# Chapter 1\n\nSome terms.\n\n",
+                "# A Section\n",
+                "1 Some terms\n",
+                "2 terms\n",
+            ]
+        );
+    }
+}
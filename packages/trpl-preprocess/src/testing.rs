@@ -0,0 +1,345 @@
+//! A harness for testing preprocessors against miniature books, the way
+//! `mdbook build` runs them: each book is a directory with a `book.toml` and a
+//! `src/SUMMARY.md`, and goes to the preprocessor as mdBook's JSON.
+//!
+//! A preprocessor's binary can be run on a book the same way, and the book it
+//! sends back checked against snapshots kept next to the book, in
+//! `snapshots/<renderer>.json`. To update the snapshots after changing what
+//! the preprocessor does, run its tests with `BLESS=1` set, and review the
+//! changes to them like any other change.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use mdbook::{
+    book::Book,
+    preprocess::{CmdPreprocessor, PreprocessorContext},
+    BookItem, MDBook,
+};
+use serde_json::json;
+
+use crate::TrplPreprocessor;
+
+/// A book loaded from a fixture directory.
+pub struct Fixture {
+    book: MDBook,
+}
+
+impl Fixture {
+    /// Load the book at `root`, which must have a chapter file for every entry
+    /// in its summary: mdBook would otherwise create them.
+    pub fn load(root: impl AsRef<Path>) -> Result<Fixture, String> {
+        let root = root.as_ref();
+        MDBook::load(root)
+            .map(|book| Fixture { book })
+            .map_err(|e| format!("Could not load {}: {e}", root.display()))
+    }
+
+    /// The JSON mdBook sends a preprocessor which is running for `renderer`.
+    pub fn input(&self, renderer: &str) -> String {
+        let ctx = json!({
+            "root": self.book.root,
+            "config": self.book.config,
+            "renderer": renderer,
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        });
+        serde_json::to_string(&(ctx, &self.book.book))
+            .expect("books can always be serialized")
+    }
+
+    /// The context and book a preprocessor gets from mdBook when it is running
+    /// for `renderer`.
+    pub fn parse(&self, renderer: &str) -> (PreprocessorContext, Book) {
+        CmdPreprocessor::parse_input(self.input(renderer).as_bytes())
+            .expect("mdBook's JSON can always be parsed")
+    }
+
+    /// Run `preprocessor` on the book for `renderer`, through the same JSON
+    /// it gets from and gives back to mdBook.
+    pub fn run(
+        &self,
+        preprocessor: &impl TrplPreprocessor,
+        renderer: &str,
+    ) -> Result<Book, String> {
+        let mut output = vec![];
+        preprocessor
+            .preprocess_json(self.input(renderer).as_bytes(), &mut output)?;
+        serde_json::from_slice(&output).map_err(|e| format!("{e}"))
+    }
+
+    /// Run the preprocessor binary at `bin` on the book for `renderer`,
+    /// exactly as mdBook does: with the book's JSON on its stdin, and the
+    /// preprocessed book's JSON back on its stdout.
+    pub fn run_binary(
+        &self,
+        bin: impl AsRef<Path>,
+        renderer: &str,
+    ) -> Result<Book, String> {
+        let bin = bin.as_ref();
+        let mut child = Command::new(bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not run {}: {e}", bin.display()))?;
+
+        // Dropping stdin once the book is written closes it, which the
+        // preprocessor needs to see to stop reading.
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(self.input(renderer).as_bytes())
+                .map_err(|e| {
+                    format!("Could not write to {}: {e}", bin.display())
+                })?;
+        }
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Could not run {}: {e}", bin.display()))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed with {}:\n{}",
+                bin.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("{e}"))
+    }
+
+    /// Run the preprocessor binary at `bin` on the book for each of
+    /// `renderers`, and check that the book it sends back matches the
+    /// snapshot for that renderer, or write the snapshot instead if `BLESS`
+    /// is set.
+    ///
+    /// # Panics
+    ///
+    /// If the binary fails, or any snapshot is missing or does not match,
+    /// after trying every renderer.
+    pub fn assert_snapshots(&self, bin: impl AsRef<Path>, renderers: &[&str]) {
+        let bless = env::var_os("BLESS").is_some();
+        let mut failures = vec![];
+        let mut outdated = false;
+        for renderer in renderers {
+            let book = match self.run_binary(&bin, renderer) {
+                Ok(book) => book,
+                Err(error) => {
+                    failures.push(format!("{renderer}: {error}"));
+                    continue;
+                }
+            };
+            let actual = serde_json::to_string_pretty(&book)
+                .expect("books can always be serialized")
+                + "\n";
+
+            let path = self.snapshot(renderer);
+            if bless {
+                if let Err(e) = fs::create_dir_all(path.parent().unwrap())
+                    .and_then(|_| fs::write(&path, &actual))
+                {
+                    failures.push(format!(
+                        "Could not write {}: {e}",
+                        path.display()
+                    ));
+                }
+                continue;
+            }
+
+            match fs::read_to_string(&path) {
+                Ok(expected) => {
+                    if let Some(mismatch) = mismatch(&path, &expected, &actual)
+                    {
+                        failures.push(mismatch);
+                        outdated = true;
+                    }
+                }
+                Err(e) => {
+                    failures.push(format!(
+                        "Could not read {}: {e}",
+                        path.display()
+                    ));
+                    outdated = true;
+                }
+            }
+        }
+
+        if outdated {
+            failures.push(String::from(
+                "\nIf the changes are expected, run the tests again with BLESS=1 to update the snapshots.",
+            ));
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    /// Where the snapshot of the preprocessed book for `renderer` is.
+    pub fn snapshot(&self, renderer: &str) -> PathBuf {
+        self.book
+            .root
+            .join("snapshots")
+            .join(format!("{renderer}.json"))
+    }
+}
+
+/// Where `actual` first differs from the snapshot at `path`, if anywhere.
+fn mismatch(path: &Path, expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => {
+                let show = |l: Option<&str>| {
+                    l.map_or_else(
+                        || String::from("the end"),
+                        |l| format!("`{}`", l.trim()),
+                    )
+                };
+                return Some(format!(
+                    "{}:{line}: expected {}, got {}",
+                    path.display(),
+                    show(e),
+                    show(a)
+                ));
+            }
+        }
+    }
+}
+
+/// Each chapter in `book`, as its path and its content, in order.
+pub fn chapters(book: &Book) -> Vec<(PathBuf, String)> {
+    book.iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => Some((
+                chapter.path.clone().unwrap_or_default(),
+                chapter.content.clone(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::{errors::Result, preprocess::Preprocessor};
+
+    use super::*;
+    use crate::config::ConfigTable;
+
+    const BOOK: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/book");
+
+    /// Adds the `suffix` from its config, and the renderer, to every chapter.
+    struct Suffix;
+
+    impl Preprocessor for Suffix {
+        fn name(&self) -> &str {
+            "suffix"
+        }
+
+        fn run(
+            &self,
+            ctx: &PreprocessorContext,
+            mut book: Book,
+        ) -> Result<Book> {
+            let config = ConfigTable::from_context(ctx, self.name());
+            let suffix = match config {
+                Some(config) => config.str("suffix")?.unwrap_or_default(),
+                None => "",
+            };
+            book.for_each_mut(|item| {
+                if let BookItem::Chapter(chapter) = item {
+                    chapter
+                        .content
+                        .push_str(&format!("{suffix} {}\n", ctx.renderer));
+                }
+            });
+            Ok(book)
+        }
+    }
+
+    impl TrplPreprocessor for Suffix {}
+
+    #[test]
+    fn runs_through_json() {
+        let fixture = Fixture::load(BOOK).unwrap();
+        let book = fixture.run(&Suffix, "markdown").unwrap();
+        assert_eq!(
+            chapters(&book),
+            vec![
+                (
+                    PathBuf::from("chapter_1.md"),
+                    String::from(
+                        "# Chapter 1\n\nSome text.\n\nSuffixed. markdown\n"
+                    )
+                ),
+                (
+                    PathBuf::from("chapter_1/section.md"),
+                    String::from("# A Section\nSuffixed. markdown\n")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        struct Failing;
+
+        impl Preprocessor for Failing {
+            fn name(&self) -> &str {
+                "failing"
+            }
+
+            fn run(&self, _: &PreprocessorContext, _: Book) -> Result<Book> {
+                Err(mdbook::errors::Error::msg("It broke"))
+            }
+        }
+
+        impl TrplPreprocessor for Failing {
+            fn report(&self, error: mdbook::errors::Error) -> String {
+                format!("Reported: {error}")
+            }
+        }
+
+        let fixture = Fixture::load(BOOK).unwrap();
+        assert_eq!(
+            fixture.run(&Failing, "html").unwrap_err(),
+            "Reported: It broke"
+        );
+    }
+
+    #[test]
+    fn missing_binary() {
+        let fixture = Fixture::load(BOOK).unwrap();
+        let error = fixture.run_binary("no/such/binary", "html").unwrap_err();
+        assert!(error.starts_with("Could not run no/such/binary"), "{error}");
+    }
+
+    #[test]
+    fn snapshot_mismatches() {
+        let path = Path::new("snapshots/html.json");
+        assert_eq!(mismatch(path, "{\n}\n", "{\n}\n"), None);
+        assert_eq!(
+            mismatch(path, "{\n  \"a\": 1\n}\n", "{\n  \"a\": 2\n}\n").unwrap(),
+            "snapshots/html.json:2: expected `\"a\": 1`, got `\"a\": 2`"
+        );
+        assert_eq!(
+            mismatch(path, "{\n}\n", "{\n}\n\n").unwrap(),
+            "snapshots/html.json:3: expected the end, got ``"
+        );
+    }
+
+    #[test]
+    fn missing_book() {
+        let error = Fixture::load("no/such/book").err().unwrap();
+        assert!(error.starts_with("Could not load no/such/book"), "{error}");
+    }
+}